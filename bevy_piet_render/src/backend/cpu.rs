@@ -0,0 +1,477 @@
+use std::mem;
+
+use ab_glyph::{Font, FontRef, OutlineCurve};
+use kurbo::{Affine, BezPath, PathEl, Point, Size};
+use piet_gpu::Color;
+use tiny_skia::{
+    FillRule, Paint, Path, PathBuilder, Pixmap, Stroke, Transform,
+};
+
+use super::RenderBackend;
+use crate::{
+    HeadlessFrame, HeadlessImage, PietRenderError, PietRenderSettings,
+    RenderViewport,
+};
+
+/// Reference rasterizer that renders on the CPU with tiny-skia and writes
+/// every frame into a [`HeadlessFrame`]. It needs no GPU at all, which makes
+/// it usable in tests and on machines without a supported device. Text is
+/// drawn in white with the bundled Noto Sans font, so it only roughly matches
+/// the glyphs of piet-gpu.
+pub struct CpuBackend {
+    pixmap: Pixmap,
+    /// Saved transforms, the last one is the current transform.
+    transforms: Vec<Affine>,
+    headless_frame: HeadlessFrame,
+    clear_color: tiny_skia::Color,
+    /// Copy the next ended frame for a screenshot.
+    capture_requested: bool,
+    captured: Vec<HeadlessImage>,
+    /// The pixmap and transforms of the frame while drawing into an image.
+    frame: Option<(Pixmap, Vec<Affine>)>,
+    /// Images ended in the current frame, until the frame ends.
+    ended_images: Vec<(u64, HeadlessImage)>,
+    /// Images rendered in ended frames, until taken.
+    rendered_images: Vec<(u64, HeadlessImage)>,
+    font: Option<FontRef<'static>>,
+}
+
+impl CpuBackend {
+    pub fn new(
+        viewport: &RenderViewport,
+        settings: &PietRenderSettings,
+        headless_frame: HeadlessFrame,
+    ) -> Result<Self, PietRenderError> {
+        let (width, height) = viewport.render_size();
+        Ok(Self {
+            pixmap: new_pixmap(width as u32, height as u32)?,
+            transforms: vec![Affine::IDENTITY],
+            headless_frame,
+            clear_color: to_skia_color(&settings.clear_color),
+            capture_requested: false,
+            captured: Vec::new(),
+            frame: None,
+            ended_images: Vec::new(),
+            rendered_images: Vec::new(),
+            font: FontRef::try_from_slice(notosans::REGULAR_TTF).ok(),
+        })
+    }
+
+    fn current_transform(&self) -> Transform {
+        let [a, b, c, d, e, f] = self.transforms.last().unwrap().as_coeffs();
+        Transform::from_row(
+            a as f32, b as f32, c as f32, d as f32, e as f32, f as f32,
+        )
+    }
+}
+
+/// Create a pixmap, which fails for an empty or too large size.
+fn new_pixmap(width: u32, height: u32) -> Result<Pixmap, PietRenderError> {
+    Pixmap::new(width, height).ok_or_else(|| {
+        PietRenderError::DeviceCreation(format!(
+            "invalid CPU render target size {}x{}",
+            width, height
+        ))
+    })
+}
+
+/// Convert a kurbo path into a tiny-skia path. Returns `None` for paths
+/// tiny-skia considers empty.
+fn to_skia_path(path: &BezPath) -> Option<Path> {
+    let mut builder = PathBuilder::new();
+    for el in path.elements() {
+        match *el {
+            PathEl::MoveTo(p) => builder.move_to(p.x as f32, p.y as f32),
+            PathEl::LineTo(p) => builder.line_to(p.x as f32, p.y as f32),
+            PathEl::QuadTo(p1, p2) => builder.quad_to(
+                p1.x as f32,
+                p1.y as f32,
+                p2.x as f32,
+                p2.y as f32,
+            ),
+            PathEl::CurveTo(p1, p2, p3) => builder.cubic_to(
+                p1.x as f32,
+                p1.y as f32,
+                p2.x as f32,
+                p2.y as f32,
+                p3.x as f32,
+                p3.y as f32,
+            ),
+            PathEl::ClosePath => builder.close(),
+        }
+    }
+    builder.finish()
+}
+
+/// Lay out `text` in lines with the top left corner at the origin and call
+/// `glyph` with the outline of every glyph, in layout coordinates. Returns the
+/// size of the layout.
+fn layout_text(
+    font: &FontRef,
+    text: &str,
+    font_size: f64,
+    mut glyph: impl FnMut(&[OutlineCurve], Affine),
+) -> Size {
+    let scale = font_size / font.units_per_em().unwrap_or(1000.0) as f64;
+    let ascent = font.ascent_unscaled() as f64 * scale;
+    let line_height = (font.ascent_unscaled() - font.descent_unscaled()
+        + font.line_gap_unscaled()) as f64
+        * scale;
+
+    let mut width: f64 = 0.0;
+    let mut lines = 0;
+    for (line_idx, line) in text.lines().enumerate() {
+        let baseline = line_idx as f64 * line_height + ascent;
+        let mut x = 0.0;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                x += font.kern_unscaled(previous, id) as f64 * scale;
+            }
+            if let Some(outline) = font.outline(id) {
+                // Font units are Y-up with the origin on the baseline.
+                let affine =
+                    Affine::new([scale, 0.0, 0.0, -scale, x, baseline]);
+                glyph(&outline.curves, affine);
+            }
+            x += font.h_advance_unscaled(id) as f64 * scale;
+            previous = Some(id);
+        }
+        width = width.max(x);
+        lines = line_idx + 1;
+    }
+    Size::new(width, lines as f64 * line_height)
+}
+
+/// Append glyph outline curves to `path`, starting a new subpath wherever a
+/// curve doesn't continue the previous one.
+fn append_outline(path: &mut BezPath, curves: &[OutlineCurve], affine: Affine) {
+    let point =
+        |p: ab_glyph::Point| affine * Point::new(p.x as f64, p.y as f64);
+    let mut last = None;
+    for curve in curves {
+        let (start, end) = match *curve {
+            OutlineCurve::Line(p0, p1) => (p0, p1),
+            OutlineCurve::Quad(p0, _, p2) => (p0, p2),
+            OutlineCurve::Cubic(p0, _, _, p3) => (p0, p3),
+        };
+        if last != Some(start) {
+            if last.is_some() {
+                path.close_path();
+            }
+            path.move_to(point(start));
+        }
+        match *curve {
+            OutlineCurve::Line(_, p1) => path.line_to(point(p1)),
+            OutlineCurve::Quad(_, p1, p2) => path.quad_to(point(p1), point(p2)),
+            OutlineCurve::Cubic(_, p1, p2, p3) => {
+                path.curve_to(point(p1), point(p2), point(p3))
+            }
+        }
+        last = Some(end);
+    }
+    if last.is_some() {
+        path.close_path();
+    }
+}
+
+/// Append the pixels of `pixmap` to `data` as straight alpha RGBA8.
+fn read_pixels(pixmap: &Pixmap, data: &mut Vec<u8>) {
+    for pixel in pixmap.pixels() {
+        let color = pixel.demultiply();
+        data.extend_from_slice(&[
+            color.red(),
+            color.green(),
+            color.blue(),
+            color.alpha(),
+        ]);
+    }
+}
+
+fn to_skia_color(color: &Color) -> tiny_skia::Color {
+    let (r, g, b, a) = color.as_rgba8();
+    tiny_skia::Color::from_rgba8(r, g, b, a)
+}
+
+fn to_skia_paint(color: &Color) -> Paint<'static> {
+    let (r, g, b, a) = color.as_rgba8();
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(r, g, b, a);
+    paint.anti_alias = true;
+    paint
+}
+
+impl RenderBackend for CpuBackend {
+    fn begin_frame(&mut self) {
+        // Drop whatever was drawn into images of a frame that never ended.
+        if let Some((pixmap, _)) = self.frame.take() {
+            self.pixmap = pixmap;
+        }
+        self.ended_images.clear();
+
+        self.pixmap.fill(self.clear_color);
+        self.transforms.clear();
+        self.transforms.push(Affine::IDENTITY);
+    }
+
+    fn save(&mut self) {
+        let current = *self.transforms.last().unwrap();
+        self.transforms.push(current);
+    }
+
+    fn restore(&mut self) {
+        if self.transforms.len() > 1 {
+            self.transforms.pop();
+        }
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        let current = self.transforms.last_mut().unwrap();
+        *current = *current * transform;
+    }
+
+    fn fill(&mut self, path: &BezPath, color: &Color) {
+        if let Some(path) = to_skia_path(path) {
+            let transform = self.current_transform();
+            self.pixmap.fill_path(
+                &path,
+                &to_skia_paint(color),
+                FillRule::Winding,
+                transform,
+                None,
+            );
+        }
+    }
+
+    fn stroke(&mut self, path: &BezPath, color: &Color, width: f64) {
+        if let Some(path) = to_skia_path(path) {
+            let transform = self.current_transform();
+            let stroke = Stroke {
+                width: width as f32,
+                ..Default::default()
+            };
+            self.pixmap.stroke_path(
+                &path,
+                &to_skia_paint(color),
+                &stroke,
+                transform,
+                None,
+            );
+        }
+    }
+
+    fn draw_text(&mut self, text: &str, font_size: f64, origin: Point) {
+        let font = match &self.font {
+            Some(font) => font,
+            None => return,
+        };
+        let mut path = BezPath::new();
+        layout_text(font, text, font_size, |curves, affine| {
+            append_outline(
+                &mut path,
+                curves,
+                Affine::translate(origin.to_vec2()) * affine,
+            )
+        });
+        self.fill(&path, &Color::WHITE);
+    }
+
+    fn end_frame(&mut self, frame: usize) -> Result<(), PietRenderError> {
+        let mut image = self.headless_frame.lock();
+        image.width = self.pixmap.width();
+        image.height = self.pixmap.height();
+        image.frame = frame;
+        image.data.clear();
+        read_pixels(&self.pixmap, &mut image.data);
+        drop(image);
+
+        if self.capture_requested {
+            let mut capture = HeadlessImage {
+                width: self.pixmap.width(),
+                height: self.pixmap.height(),
+                frame,
+                ..Default::default()
+            };
+            read_pixels(&self.pixmap, &mut capture.data);
+            self.captured.push(capture);
+            self.capture_requested = false;
+        }
+
+        for (id, mut image) in self.ended_images.drain(..) {
+            image.frame = frame;
+            self.rendered_images.push((id, image));
+        }
+        Ok(())
+    }
+
+    fn capture_next_frame(&mut self) -> Result<(), PietRenderError> {
+        self.capture_requested = true;
+        Ok(())
+    }
+
+    fn take_captured_frames(&mut self) -> Vec<HeadlessImage> {
+        mem::take(&mut self.captured)
+    }
+
+    fn begin_image(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(), PietRenderError> {
+        let mut pixmap = new_pixmap(width.max(1), height.max(1))?;
+        pixmap.fill(self.clear_color);
+
+        // Draw into the image instead of the frame for the time being.
+        let frame_pixmap = mem::replace(&mut self.pixmap, pixmap);
+        let frame_transforms =
+            mem::replace(&mut self.transforms, vec![Affine::IDENTITY]);
+        self.frame = Some((frame_pixmap, frame_transforms));
+        Ok(())
+    }
+
+    fn end_image(&mut self, id: u64) {
+        let (frame_pixmap, frame_transforms) = match self.frame.take() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let pixmap = mem::replace(&mut self.pixmap, frame_pixmap);
+        self.transforms = frame_transforms;
+
+        let mut image = HeadlessImage {
+            width: pixmap.width(),
+            height: pixmap.height(),
+            ..Default::default()
+        };
+        read_pixels(&pixmap, &mut image.data);
+        self.ended_images.push((id, image));
+    }
+
+    fn take_rendered_images(&mut self) -> Vec<(u64, HeadlessImage)> {
+        mem::take(&mut self.rendered_images)
+    }
+
+    fn resize(
+        &mut self,
+        viewport: &RenderViewport,
+    ) -> Result<(), PietRenderError> {
+        let (width, height) = viewport.render_size();
+        if self.pixmap.width() != width as u32
+            || self.pixmap.height() != height as u32
+        {
+            self.pixmap = new_pixmap(width as u32, height as u32)?;
+        }
+        Ok(())
+    }
+
+    fn configure(
+        &mut self,
+        settings: &PietRenderSettings,
+    ) -> Result<(), PietRenderError> {
+        self.clear_color = to_skia_color(&settings.clear_color);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::{Rect, Shape};
+
+    use super::*;
+    use crate::render::estimate_text_bounds;
+
+    fn backend(width: u32, height: u32) -> (CpuBackend, HeadlessFrame) {
+        let viewport = RenderViewport {
+            physical_width: width,
+            physical_height: height,
+            scale_factor: 1.0,
+            render_scale: 1.0,
+        };
+        let settings = PietRenderSettings {
+            clear_color: Color::rgb8(0, 0, 0xff),
+            ..Default::default()
+        };
+        let frame = HeadlessFrame::default();
+        let backend =
+            CpuBackend::new(&viewport, &settings, frame.clone()).unwrap();
+        (backend, frame)
+    }
+
+    fn pixel(image: &HeadlessImage, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * image.width + x) * 4) as usize;
+        image.data[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn fills_paths_with_the_current_transform() {
+        let (mut backend, frame) = backend(16, 16);
+        backend.begin_frame();
+        backend.transform(Affine::translate((8.0, 8.0)));
+        let rect = Rect::new(0.0, 0.0, 8.0, 8.0).to_path(0.1);
+        backend.fill(&rect, &Color::rgb8(0xff, 0, 0));
+        backend.end_frame(3).unwrap();
+
+        let image = frame.lock();
+        assert_eq!((image.width, image.height, image.frame), (16, 16, 3));
+        assert_eq!(pixel(&image, 12, 12), [0xff, 0, 0, 0xff]);
+        assert_eq!(pixel(&image, 4, 4), [0, 0, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn renders_images_when_the_frame_ends() {
+        let (mut backend, frame) = backend(16, 16);
+        let red = Rect::new(0.0, 0.0, 4.0, 4.0).to_path(0.1);
+        backend.begin_frame();
+        backend.begin_image(8, 4).unwrap();
+        backend.fill(&red, &Color::rgb8(0xff, 0, 0));
+        backend.end_image(7);
+        assert!(backend.take_rendered_images().is_empty());
+        backend.end_frame(2).unwrap();
+
+        // The image is drawn apart from the frame.
+        assert_eq!(pixel(&frame.lock(), 2, 2), [0, 0, 0xff, 0xff]);
+        let images = backend.take_rendered_images();
+        assert_eq!(images.len(), 1);
+        let (id, image) = &images[0];
+        assert_eq!((*id, image.width, image.height, image.frame), (7, 8, 4, 2));
+        assert_eq!(pixel(image, 2, 2), [0xff, 0, 0, 0xff]);
+        assert_eq!(pixel(image, 6, 2), [0, 0, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn estimated_text_bounds_cover_the_layout() {
+        let font = FontRef::try_from_slice(notosans::REGULAR_TTF).unwrap();
+        for text in ["Hi", "WWW\nmm", "Ég ýgj", "\n\n|"] {
+            let mut bounds: Option<Rect> = None;
+            layout_text(&font, text, 40.0, |curves, affine| {
+                let mut path = BezPath::new();
+                append_outline(&mut path, curves, affine);
+                let glyph = path.bounding_box();
+                bounds = Some(bounds.map_or(glyph, |b| b.union(glyph)));
+            });
+            let estimate = estimate_text_bounds(text, 40.0);
+            let bounds = bounds.unwrap();
+            assert_eq!(estimate.union(bounds), estimate, "{}", text);
+        }
+    }
+
+    #[test]
+    fn draws_text() {
+        let (mut backend, frame) = backend(64, 48);
+        let font = backend.font.clone().unwrap();
+        let size = layout_text(&font, "Hi", 24.0, |_, _| {});
+        assert!(size.width > 0.0 && size.width < 64.0);
+        assert!(size.height > 24.0 && size.height < 48.0);
+
+        backend.begin_frame();
+        backend.draw_text("Hi", 24.0, Point::ORIGIN);
+        backend.end_frame(0).unwrap();
+
+        let image = frame.lock();
+        let white = (0..48)
+            .flat_map(|y| (0..64).map(move |x| (x, y)))
+            .filter(|&(x, y)| pixel(&image, x, y) == [0xff; 4])
+            .count();
+        assert!(white > 20, "only {} text pixels", white);
+    }
+}
@@ -0,0 +1,819 @@
+use std::mem;
+
+use bevy::prelude::*;
+use kurbo::{Affine, BezPath, Point, Rect};
+use piet_gpu::{
+    Color, PietGpuRenderContext, RenderContext, Renderer, Text, TextAttribute,
+    TextLayoutBuilder,
+};
+use piet_gpu_hal::{
+    Buffer, BufferUsage, CmdBuf, Error, ImageLayout, Instance, QueryPool,
+    Semaphore, Session, SubmittedCmdBuf, Surface, Swapchain,
+};
+
+use super::RenderBackend;
+use crate::{
+    GpuTimings, HeadlessFrame, HeadlessImage, PietRenderError,
+    PietRenderSettings, RenderViewport,
+};
+
+/// `Renderer::record` writes timestamps 0 to 7 around its pipeline stages, we
+/// add one more after copying the image to the output.
+const NUM_QUERIES: u32 = 9;
+const OUTPUT_QUERY: u32 = 8;
+
+/// Where a recorded frame ends up once the GPU is done with it.
+enum RenderOutput {
+    /// Blit into the next swapchain image and present it. The surface is kept
+    /// around to recreate the swapchain when the window is resized, and the
+    /// swapchain is `None` between dropping the old one and creating the new
+    /// one.
+    Swapchain {
+        swapchain: Option<Swapchain>,
+        surface: Surface,
+    },
+    /// Copy into a host visible buffer per frame in flight and from there
+    /// into the [`HeadlessFrame`].
+    Readback {
+        readback_bufs: Vec<Buffer>,
+        headless_frame: HeadlessFrame,
+    },
+}
+
+/// A renderer of its own for rendering into images, with a readback buffer
+/// and a query pool per frame in flight. The images of a frame each use the
+/// target at their position, so a target only has to be recreated when the
+/// image at its position changes size.
+struct OffscreenTarget {
+    size: (usize, usize),
+    renderer: Renderer,
+    readback_bufs: Vec<Buffer>,
+    query_pools: Vec<QueryPool>,
+    /// Id and frame of the image rendered per frame in flight, read back
+    /// once that frame finished.
+    in_flight: Vec<Option<(u64, usize)>>,
+}
+
+impl OffscreenTarget {
+    unsafe fn new(
+        session: &Session,
+        size: (usize, usize),
+        frames_in_flight: usize,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            size,
+            renderer: Renderer::new(session, size.0, size.1, frames_in_flight)?,
+            readback_bufs: create_readback_bufs(
+                session,
+                size,
+                frames_in_flight,
+            )?,
+            query_pools: create_query_pools(session, frames_in_flight)?,
+            in_flight: (0..frames_in_flight).map(|_| None).collect(),
+        })
+    }
+}
+
+/// An image drawn in the current frame, rendered when the frame ends.
+struct EndedImage {
+    id: u64,
+    size: (usize, usize),
+    ctx: PietGpuRenderContext,
+}
+
+/// A copy of a frame for a screenshot, in flight with the frame.
+struct Capture {
+    buf: Buffer,
+    size: (usize, usize),
+    frame: usize,
+}
+
+/// Renders with piet-gpu on a Vulkan, Metal or DX12 device.
+pub struct GpuBackend {
+    ctx: PietGpuRenderContext,
+    present_semaphores: Vec<Semaphore>,
+    query_pools: Vec<QueryPool>,
+    cmd_bufs: Vec<Option<CmdBuf>>,
+    /// Command buffers in flight, with the number of their frame.
+    submitted: Vec<Option<(SubmittedCmdBuf, usize)>>,
+    session: Session,
+    output: RenderOutput,
+    renderer: Renderer,
+    /// Physical output size the resources were created for.
+    physical_size: (usize, usize),
+    /// Render size the resources were created for.
+    render_size: (usize, usize),
+    frames_in_flight: usize,
+    clear_color: Color,
+    /// The size of the image being drawn and the context of the frame, from
+    /// `begin_image` to `end_image`.
+    image: Option<((usize, usize), PietGpuRenderContext)>,
+    /// Images drawn in the current frame.
+    ended_images: Vec<EndedImage>,
+    /// Renderers for the images, by their position in the frame.
+    offscreen_targets: Vec<OffscreenTarget>,
+    /// Images read back from the GPU, until taken.
+    rendered_images: Vec<(u64, HeadlessImage)>,
+    /// Copy the next ended frame for a screenshot.
+    capture_requested: bool,
+    /// Screenshot copies per frame in flight.
+    captures: Vec<Option<Capture>>,
+    /// Screenshot frames read back from the GPU, until taken.
+    captured: Vec<HeadlessImage>,
+    /// Stage timings of the last frame the GPU finished, until taken.
+    gpu_timings: Option<GpuTimings>,
+    // Declared last to keep the instance alive until everything created from
+    // it has been dropped.
+    instance: Instance,
+}
+
+impl GpuBackend {
+    /// Create a backend presenting to `window`.
+    pub fn windowed(
+        window: &Window,
+        viewport: &RenderViewport,
+        settings: &PietRenderSettings,
+    ) -> Result<Self, PietRenderError> {
+        let raw_window_handle =
+            unsafe { window.raw_window_handle().get_handle() };
+        let (instance, surface) =
+            Instance::new(Some(&raw_window_handle), Default::default())
+                .map_err(|e| {
+                    PietRenderError::InstanceCreation(e.to_string())
+                })?;
+        let surface = surface.ok_or_else(|| {
+            PietRenderError::InstanceCreation(
+                "no surface for the window".to_string(),
+            )
+        })?;
+        let device = unsafe {
+            instance
+                .device(Some(&surface))
+                .map_err(|e| PietRenderError::DeviceCreation(e.to_string()))?
+        };
+        let swapchain = unsafe {
+            instance
+                .swapchain(
+                    viewport.physical_width as usize,
+                    viewport.physical_height as usize,
+                    &device,
+                    &surface,
+                )
+                .map_err(|e| PietRenderError::DeviceCreation(e.to_string()))?
+        };
+        let session = Session::new(device);
+
+        Self::new(
+            instance,
+            session,
+            RenderOutput::Swapchain {
+                swapchain: Some(swapchain),
+                surface,
+            },
+            viewport,
+            settings,
+        )
+    }
+
+    /// Create a backend without a surface, which copies every frame back
+    /// into `headless_frame`. Nothing is blitted in headless mode, so the
+    /// read back image has the render size.
+    pub fn headless(
+        viewport: &RenderViewport,
+        settings: &PietRenderSettings,
+        headless_frame: HeadlessFrame,
+    ) -> Result<Self, PietRenderError> {
+        let (instance, _) = Instance::new(None, Default::default())
+            .map_err(|e| PietRenderError::InstanceCreation(e.to_string()))?;
+        let device = unsafe {
+            instance
+                .device(None)
+                .map_err(|e| PietRenderError::DeviceCreation(e.to_string()))?
+        };
+        let session = Session::new(device);
+
+        let readback_bufs = create_readback_bufs(
+            &session,
+            viewport.render_size(),
+            settings.frames_in_flight,
+        )
+        .map_err(|e| PietRenderError::DeviceCreation(e.to_string()))?;
+
+        Self::new(
+            instance,
+            session,
+            RenderOutput::Readback {
+                readback_bufs,
+                headless_frame,
+            },
+            viewport,
+            settings,
+        )
+    }
+
+    fn new(
+        instance: Instance,
+        session: Session,
+        output: RenderOutput,
+        viewport: &RenderViewport,
+        settings: &PietRenderSettings,
+    ) -> Result<Self, PietRenderError> {
+        let (width, height) = viewport.render_size();
+        let frames_in_flight = settings.frames_in_flight.max(1);
+        let device_error =
+            |e: Error| PietRenderError::DeviceCreation(e.to_string());
+
+        let query_pools = create_query_pools(&session, frames_in_flight)
+            .map_err(device_error)?;
+        let cmd_bufs = (0..frames_in_flight).map(|_| None).collect();
+        let submitted = (0..frames_in_flight).map(|_| None).collect();
+        let captures = (0..frames_in_flight).map(|_| None).collect();
+
+        unsafe {
+            let present_semaphores = (0..frames_in_flight)
+                .map(|_| session.create_semaphore())
+                .collect::<Result<Vec<_>, Error>>()
+                .map_err(device_error)?;
+
+            let renderer =
+                Renderer::new(&session, width, height, frames_in_flight)
+                    .map_err(device_error)?;
+
+            Ok(Self {
+                ctx: PietGpuRenderContext::new(),
+                present_semaphores,
+                query_pools,
+                cmd_bufs,
+                submitted,
+                session,
+                output,
+                renderer,
+                physical_size: (
+                    viewport.physical_width as usize,
+                    viewport.physical_height as usize,
+                ),
+                render_size: (width, height),
+                frames_in_flight,
+                clear_color: settings.clear_color.clone(),
+                image: None,
+                ended_images: Vec::new(),
+                offscreen_targets: Vec::new(),
+                rendered_images: Vec::new(),
+                capture_requested: false,
+                captures,
+                captured: Vec::new(),
+                gpu_timings: None,
+                instance,
+            })
+        }
+    }
+
+    /// Lay out `text`, or `None` if piet-gpu can't, in which case the text
+    /// is skipped.
+    fn text_layout(
+        &mut self,
+        text: &str,
+        font_size: f64,
+    ) -> Option<<PietGpuRenderContext as RenderContext>::TextLayout> {
+        self.ctx
+            .text()
+            .new_text_layout(text.to_string())
+            .default_attribute(TextAttribute::FontSize(font_size))
+            .build()
+            .ok()
+    }
+
+    /// piet-gpu starts from transparent black and does not implement
+    /// `clear`, so paint the clear color over the whole image instead.
+    fn fill_clear_color(&mut self, (width, height): (usize, usize)) {
+        if self.clear_color.as_rgba_u32() & 0xff != 0 {
+            self.ctx.fill(
+                Rect::new(0.0, 0.0, width as f64, height as f64),
+                &self.clear_color,
+            );
+        }
+    }
+
+    /// Block until every submitted command buffer has finished executing,
+    /// read back what they copied and take them back for reuse.
+    unsafe fn wait_idle(&mut self) -> Result<(), Error> {
+        for frame_idx in 0..self.submitted.len() {
+            self.finish_frame(frame_idx)?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the frame in flight at `frame_idx`, if there is one, take
+    /// its command buffer back and read its timings, its screenshot and
+    /// image copies and, in headless mode, the frame itself.
+    unsafe fn finish_frame(&mut self, frame_idx: usize) -> Result<(), Error> {
+        let (submitted, frame) = match self.submitted[frame_idx].take() {
+            Some(submitted) => submitted,
+            None => return Ok(()),
+        };
+        self.cmd_bufs[frame_idx] = submitted.wait()?;
+        self.read_capture(frame_idx)?;
+        self.read_images(frame_idx)?;
+        let ts = self
+            .session
+            .fetch_query_pool(&self.query_pools[frame_idx])?;
+        self.gpu_timings = GpuTimings::from_timestamps(&ts);
+
+        if let RenderOutput::Readback {
+            readback_bufs,
+            headless_frame,
+        } = &self.output
+        {
+            let mut image = headless_frame.lock();
+            readback_bufs[frame_idx].read(&mut image.data)?;
+            image.frame = frame;
+        }
+        Ok(())
+    }
+
+    /// Read the screenshot copy of a frame in flight whose command buffer
+    /// finished.
+    unsafe fn read_capture(&mut self, frame_idx: usize) -> Result<(), Error> {
+        if let Some(capture) = self.captures[frame_idx].take() {
+            let mut image = HeadlessImage {
+                width: capture.size.0 as u32,
+                height: capture.size.1 as u32,
+                frame: capture.frame,
+                ..Default::default()
+            };
+            capture.buf.read(&mut image.data)?;
+            self.captured.push(image);
+        }
+        Ok(())
+    }
+
+    /// Read the images rendered by a frame in flight whose command buffer
+    /// finished.
+    unsafe fn read_images(&mut self, frame_idx: usize) -> Result<(), Error> {
+        for target in &mut self.offscreen_targets {
+            if let Some((id, frame)) = target.in_flight[frame_idx].take() {
+                let mut image = HeadlessImage {
+                    width: target.size.0 as u32,
+                    height: target.size.1 as u32,
+                    frame,
+                    ..Default::default()
+                };
+                target.readback_bufs[frame_idx].read(&mut image.data)?;
+                self.rendered_images.push((id, image));
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure every image of the frame has an offscreen target of its
+    /// size, and drop the targets at the end that no image uses and that
+    /// have nothing in flight.
+    unsafe fn prepare_offscreen_targets(&mut self) -> Result<(), Error> {
+        let sizes: Vec<_> =
+            self.ended_images.iter().map(|image| image.size).collect();
+        for (index, &size) in sizes.iter().enumerate() {
+            match self.offscreen_targets.get(index) {
+                Some(target) if target.size == size => {}
+                Some(_) => {
+                    // Images in flight still use the old renderer.
+                    self.wait_idle()?;
+                    self.offscreen_targets[index] = OffscreenTarget::new(
+                        &self.session,
+                        size,
+                        self.frames_in_flight,
+                    )?;
+                }
+                None => {
+                    let target = OffscreenTarget::new(
+                        &self.session,
+                        size,
+                        self.frames_in_flight,
+                    )?;
+                    self.offscreen_targets.push(target);
+                }
+            }
+        }
+
+        while self.offscreen_targets.len() > sizes.len() {
+            let target = self.offscreen_targets.last().unwrap();
+            if target.in_flight.iter().any(Option::is_some) {
+                break;
+            }
+            self.offscreen_targets.pop();
+        }
+        Ok(())
+    }
+
+    /// Recreate the swapchain at `physical_size`, together with the present
+    /// semaphores, since a failed present can leave one of them signaled.
+    /// Does nothing in headless mode. The GPU has to be idle.
+    unsafe fn recreate_swapchain(
+        &mut self,
+        physical_size: (usize, usize),
+    ) -> Result<(), Error> {
+        if let RenderOutput::Swapchain { swapchain, surface } = &mut self.output
+        {
+            // The pinned piet-gpu-hal can't hand the old swapchain to the new
+            // one, and a surface only takes one swapchain at a time, so the
+            // old one has to go first.
+            *swapchain = None;
+            *swapchain = Some(self.instance.swapchain(
+                physical_size.0,
+                physical_size.1,
+                self.session.device(),
+                surface,
+            )?);
+            self.present_semaphores = (0..self.frames_in_flight)
+                .map(|_| self.session.create_semaphore())
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild everything that depends on the size of the output or on the
+    /// number of frames in flight. Command buffers are kept and only added or
+    /// dropped when the number of frames in flight changes, so resizing does
+    /// not leak them.
+    unsafe fn rebuild(
+        &mut self,
+        physical_size: (usize, usize),
+        render_size: (usize, usize),
+        frames_in_flight: usize,
+    ) -> Result<(), Error> {
+        self.wait_idle()?;
+
+        self.frames_in_flight = frames_in_flight;
+        self.cmd_bufs.resize_with(frames_in_flight, || None);
+        self.submitted.resize_with(frames_in_flight, || None);
+        self.captures.resize_with(frames_in_flight, || None);
+        // Created again with the new number of frames in flight when used.
+        self.offscreen_targets.clear();
+        self.recreate_swapchain(physical_size)?;
+        if let RenderOutput::Readback { readback_bufs, .. } = &mut self.output {
+            *readback_bufs = create_readback_bufs(
+                &self.session,
+                render_size,
+                frames_in_flight,
+            )?;
+        }
+
+        self.renderer = Renderer::new(
+            &self.session,
+            render_size.0,
+            render_size.1,
+            frames_in_flight,
+        )?;
+        self.query_pools = create_query_pools(&self.session, frames_in_flight)?;
+        self.physical_size = physical_size;
+        self.render_size = render_size;
+        Ok(())
+    }
+}
+
+fn create_query_pools(
+    session: &Session,
+    frames_in_flight: usize,
+) -> Result<Vec<QueryPool>, Error> {
+    (0..frames_in_flight)
+        .map(|_| session.create_query_pool(NUM_QUERIES))
+        .collect()
+}
+
+fn create_readback_bufs(
+    session: &Session,
+    (width, height): (usize, usize),
+    frames_in_flight: usize,
+) -> Result<Vec<Buffer>, Error> {
+    (0..frames_in_flight)
+        .map(|_| {
+            session.create_buffer(
+                (width * height * 4) as u64,
+                BufferUsage::MAP_READ | BufferUsage::COPY_DST,
+            )
+        })
+        .collect()
+}
+
+impl RenderBackend for GpuBackend {
+    fn begin_frame(&mut self) {
+        self.ctx = PietGpuRenderContext::new();
+        self.fill_clear_color(self.render_size);
+        // Drop the images of a frame that never ended.
+        self.image = None;
+        self.ended_images.clear();
+    }
+
+    fn save(&mut self) {
+        // Saving only pushes onto the state stack, which cannot fail.
+        let _ = self.ctx.save();
+    }
+
+    fn restore(&mut self) {
+        // Ignore an unbalanced restore, like the CPU backend does, the
+        // transform stays as it is.
+        let _ = self.ctx.restore();
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.ctx.transform(transform);
+    }
+
+    fn fill(&mut self, path: &BezPath, color: &Color) {
+        self.ctx.fill(path, color);
+    }
+
+    fn stroke(&mut self, path: &BezPath, color: &Color, width: f64) {
+        self.ctx.stroke(path, color, width);
+    }
+
+    fn draw_text(&mut self, text: &str, font_size: f64, origin: Point) {
+        if let Some(layout) = self.text_layout(text, font_size) {
+            self.ctx.draw_text(&layout, origin);
+        }
+    }
+
+    fn end_frame(&mut self, frame: usize) -> Result<(), PietRenderError> {
+        let cmd_buf_error =
+            |e: Error| PietRenderError::CommandBuffer(e.to_string());
+
+        unsafe {
+            let frame_idx = frame % self.frames_in_flight;
+
+            // The slot is free again once the frame rendered
+            // `frames_in_flight` frames ago finished.
+            self.finish_frame(frame_idx).map_err(cmd_buf_error)?;
+
+            self.renderer
+                .upload_render_ctx(&mut self.ctx, frame_idx)
+                .map_err(|e| PietRenderError::Upload(e.to_string()))?;
+            self.prepare_offscreen_targets()
+                .map_err(|e| PietRenderError::DeviceCreation(e.to_string()))?;
+            for (target, image) in self
+                .offscreen_targets
+                .iter_mut()
+                .zip(&mut self.ended_images)
+            {
+                target
+                    .renderer
+                    .upload_render_ctx(&mut image.ctx, frame_idx)
+                    .map_err(|e| PietRenderError::Upload(e.to_string()))?;
+            }
+
+            let session = &self.session;
+            let mut cmd_buf = match self.cmd_bufs[frame_idx].take() {
+                Some(cmd_buf) => cmd_buf,
+                None => session.cmd_buf().map_err(cmd_buf_error)?,
+            };
+            cmd_buf.begin();
+            self.renderer.record(
+                &mut cmd_buf,
+                &self.query_pools[frame_idx],
+                frame_idx,
+            );
+            for target in
+                self.offscreen_targets.iter().take(self.ended_images.len())
+            {
+                target.renderer.record(
+                    &mut cmd_buf,
+                    &target.query_pools[frame_idx],
+                    frame_idx,
+                );
+                // Image -> Buffer
+                cmd_buf.copy_image_to_buffer(
+                    &target.renderer.image_dev,
+                    &target.readback_bufs[frame_idx],
+                );
+                cmd_buf.host_barrier();
+            }
+
+            let capture = if self.capture_requested {
+                let buf = create_readback_bufs(session, self.render_size, 1)
+                    .map_err(cmd_buf_error)?
+                    .remove(0);
+                // Image -> Buffer, before it goes to the output
+                cmd_buf.copy_image_to_buffer(&self.renderer.image_dev, &buf);
+                cmd_buf.host_barrier();
+                Some(Capture {
+                    buf,
+                    size: self.render_size,
+                    frame,
+                })
+            } else {
+                None
+            };
+
+            match &mut self.output {
+                RenderOutput::Swapchain { swapchain, .. } => {
+                    let swapchain = swapchain.as_mut().ok_or_else(|| {
+                        PietRenderError::SwapchainAcquire(
+                            "the swapchain could not be recreated".to_string(),
+                        )
+                    })?;
+                    let (image_idx, acquisition_semaphore) =
+                        swapchain.next().map_err(|e| {
+                            PietRenderError::SwapchainAcquire(e.to_string())
+                        })?;
+                    let swap_image = swapchain.image(image_idx);
+
+                    // Image -> Swapchain
+                    cmd_buf.image_barrier(
+                        &swap_image,
+                        ImageLayout::Undefined,
+                        ImageLayout::BlitDst,
+                    );
+                    cmd_buf.blit_image(&self.renderer.image_dev, &swap_image);
+                    cmd_buf.image_barrier(
+                        &swap_image,
+                        ImageLayout::BlitDst,
+                        ImageLayout::Present,
+                    );
+                    cmd_buf.write_timestamp(
+                        &self.query_pools[frame_idx],
+                        OUTPUT_QUERY,
+                    );
+                    cmd_buf.finish();
+
+                    self.submitted[frame_idx] = Some((
+                        session
+                            .run_cmd_buf(
+                                cmd_buf,
+                                &[&acquisition_semaphore],
+                                &[&self.present_semaphores[frame_idx]],
+                            )
+                            .map_err(cmd_buf_error)?,
+                        frame,
+                    ));
+
+                    swapchain
+                        .present(
+                            image_idx,
+                            &[&self.present_semaphores[frame_idx]],
+                        )
+                        .map_err(|e| PietRenderError::Present(e.to_string()))?;
+                }
+                RenderOutput::Readback { readback_bufs, .. } => {
+                    // Image -> Buffer
+                    cmd_buf.copy_image_to_buffer(
+                        &self.renderer.image_dev,
+                        &readback_bufs[frame_idx],
+                    );
+                    cmd_buf.write_timestamp(
+                        &self.query_pools[frame_idx],
+                        OUTPUT_QUERY,
+                    );
+                    cmd_buf.host_barrier();
+                    cmd_buf.finish();
+
+                    self.submitted[frame_idx] = Some((
+                        session
+                            .run_cmd_buf(cmd_buf, &[], &[])
+                            .map_err(cmd_buf_error)?,
+                        frame,
+                    ));
+                }
+            }
+
+            // Read once this frame in flight finished, like the readback.
+            if capture.is_some() {
+                self.captures[frame_idx] = capture;
+                self.capture_requested = false;
+            }
+            for (target, image) in self
+                .offscreen_targets
+                .iter_mut()
+                .zip(self.ended_images.drain(..))
+            {
+                target.in_flight[frame_idx] = Some((image.id, frame));
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), PietRenderError> {
+        let pending = |frame_idx: usize| {
+            self.captures[frame_idx].is_some()
+                || matches!(self.output, RenderOutput::Readback { .. })
+                || self
+                    .offscreen_targets
+                    .iter()
+                    .any(|target| target.in_flight[frame_idx].is_some())
+        };
+        let pending: Vec<_> = (0..self.frames_in_flight)
+            .filter(|&frame_idx| pending(frame_idx))
+            .collect();
+        unsafe {
+            for frame_idx in pending {
+                self.finish_frame(frame_idx).map_err(|e| {
+                    PietRenderError::CommandBuffer(e.to_string())
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn capture_next_frame(&mut self) -> Result<(), PietRenderError> {
+        self.capture_requested = true;
+        Ok(())
+    }
+
+    fn take_captured_frames(&mut self) -> Vec<HeadlessImage> {
+        mem::take(&mut self.captured)
+    }
+
+    fn begin_image(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<(), PietRenderError> {
+        let size = (width.max(1) as usize, height.max(1) as usize);
+
+        // Encode the image into a context of its own, keeping the one of the
+        // frame.
+        let frame_ctx =
+            mem::replace(&mut self.ctx, PietGpuRenderContext::new());
+        self.image = Some((size, frame_ctx));
+        self.fill_clear_color(size);
+        Ok(())
+    }
+
+    fn end_image(&mut self, id: u64) {
+        if let Some((size, frame_ctx)) = self.image.take() {
+            let ctx = mem::replace(&mut self.ctx, frame_ctx);
+            self.ended_images.push(EndedImage { id, size, ctx });
+        }
+    }
+
+    fn take_rendered_images(&mut self) -> Vec<(u64, HeadlessImage)> {
+        mem::take(&mut self.rendered_images)
+    }
+
+    fn resize(
+        &mut self,
+        viewport: &RenderViewport,
+    ) -> Result<(), PietRenderError> {
+        let physical_size = (
+            viewport.physical_width as usize,
+            viewport.physical_height as usize,
+        );
+        let render_size = viewport.render_size();
+        if self.physical_size == physical_size
+            && self.render_size == render_size
+        {
+            return Ok(());
+        }
+
+        unsafe {
+            self.rebuild(physical_size, render_size, self.frames_in_flight)
+                .map_err(|e| PietRenderError::DeviceCreation(e.to_string()))?;
+        }
+
+        if let RenderOutput::Readback { headless_frame, .. } = &self.output {
+            let mut image = headless_frame.lock();
+            image.width = render_size.0 as u32;
+            image.height = render_size.1 as u32;
+        }
+        Ok(())
+    }
+
+    fn take_gpu_timings(&mut self) -> Option<GpuTimings> {
+        self.gpu_timings.take()
+    }
+
+    fn recover(
+        &mut self,
+        error: &PietRenderError,
+    ) -> Result<(), PietRenderError> {
+        if !error.is_recoverable() {
+            return Ok(());
+        }
+
+        // An out of date swapchain is the usual cause of acquire and present
+        // failures, so recreate it at the current size. Nothing else depends
+        // on the swapchain.
+        unsafe {
+            self.wait_idle()
+                .and_then(|_| self.recreate_swapchain(self.physical_size))
+                .map_err(|e| PietRenderError::DeviceCreation(e.to_string()))
+        }
+    }
+
+    fn configure(
+        &mut self,
+        settings: &PietRenderSettings,
+    ) -> Result<(), PietRenderError> {
+        self.clear_color = settings.clear_color.clone();
+
+        let frames_in_flight = settings.frames_in_flight.max(1);
+        if frames_in_flight != self.frames_in_flight {
+            unsafe {
+                self.rebuild(
+                    self.physical_size,
+                    self.render_size,
+                    frames_in_flight,
+                )
+                .map_err(|e| PietRenderError::DeviceCreation(e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+}
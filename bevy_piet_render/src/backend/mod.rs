@@ -0,0 +1,156 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use kurbo::{Affine, BezPath, Point};
+use piet_gpu::Color;
+
+use crate::{
+    GpuTimings, HeadlessImage, PietRenderError, PietRenderSettings,
+    RenderViewport, SceneFragment,
+};
+
+mod cpu;
+mod gpu;
+
+pub use cpu::CpuBackend;
+pub use gpu::GpuBackend;
+
+/// The interface between the [`RenderCommand`](crate::RenderCommand) stream
+/// and whatever turns it into pixels.
+///
+/// Drawing calls follow the piet model: they use the current transform,
+/// which is changed with [`transform`](RenderBackend::transform) and scoped
+/// with [`save`](RenderBackend::save) and [`restore`](RenderBackend::restore).
+pub trait RenderBackend: 'static {
+    /// Start a new frame with an empty scene and an identity transform.
+    fn begin_frame(&mut self);
+
+    /// Push the current transform onto the state stack.
+    fn save(&mut self);
+
+    /// Pop the transform pushed by the matching [`save`](Self::save).
+    fn restore(&mut self);
+
+    /// Multiply the current transform by `transform`.
+    fn transform(&mut self, transform: Affine);
+
+    fn fill(&mut self, path: &BezPath, color: &Color);
+
+    fn stroke(&mut self, path: &BezPath, color: &Color, width: f64);
+
+    /// Draw `text` with the top left corner of its layout at `origin`.
+    fn draw_text(&mut self, text: &str, font_size: f64, origin: Point);
+
+    /// Draw a fragment shared by many draws, like the paths of a vector
+    /// image used by many entities. Replays its drawing calls by default,
+    /// recorders keep a reference to it instead of copying them.
+    fn draw_fragment(&mut self, fragment: &Arc<SceneFragment>) {
+        fragment.replay(self);
+    }
+
+    /// Finish the frame started by [`begin_frame`](Self::begin_frame) and
+    /// hand it to the output. May be called again for the same frame after
+    /// [`recover`](Self::recover) when the frame is retried.
+    fn end_frame(&mut self, frame: usize) -> Result<(), PietRenderError>;
+
+    /// Recreate everything that depends on the size of the output. Called
+    /// every time the viewport changes, so it should do nothing if the size
+    /// stays the same.
+    fn resize(
+        &mut self,
+        viewport: &RenderViewport,
+    ) -> Result<(), PietRenderError>;
+
+    /// Apply [`PietRenderSettings`] that changed after the backend was
+    /// created.
+    fn configure(
+        &mut self,
+        settings: &PietRenderSettings,
+    ) -> Result<(), PietRenderError>;
+
+    /// Draw into a new image of `width` by `height` pixels instead of the
+    /// frame, starting with an empty scene and an identity transform, until
+    /// [`end_image`](Self::end_image). Called between
+    /// [`begin_frame`](Self::begin_frame) and [`end_frame`](Self::end_frame)
+    /// for every camera rendering into an image.
+    fn begin_image(
+        &mut self,
+        _width: u32,
+        _height: u32,
+    ) -> Result<(), PietRenderError> {
+        Err(PietRenderError::Unsupported(
+            "rendering into images".to_string(),
+        ))
+    }
+
+    /// Finish the image started by [`begin_image`](Self::begin_image) and
+    /// go back to drawing the frame. The image is rendered together with the
+    /// frame and comes out of
+    /// [`take_rendered_images`](Self::take_rendered_images) under `id` once
+    /// the backend has read it back.
+    fn end_image(&mut self, _id: u64) {}
+
+    /// Images rendered since the last call, with the id they were ended
+    /// with, as RGBA8.
+    fn take_rendered_images(&mut self) -> Vec<(u64, HeadlessImage)> {
+        Vec::new()
+    }
+
+    /// Read back everything frames still in flight copy for screenshots,
+    /// image targets or the headless frame, waiting for the GPU if needed.
+    /// Called instead of [`end_frame`](Self::end_frame) when nothing was
+    /// drawn, since the copies would otherwise wait for a later frame that
+    /// may never come.
+    fn flush(&mut self) -> Result<(), PietRenderError> {
+        Ok(())
+    }
+
+    /// Copy the next frame finished with [`end_frame`](Self::end_frame)
+    /// for a screenshot. The copy comes out of
+    /// [`take_captured_frames`](Self::take_captured_frames) once the
+    /// backend has read it back.
+    fn capture_next_frame(&mut self) -> Result<(), PietRenderError> {
+        Err(PietRenderError::Unsupported("screenshots".to_string()))
+    }
+
+    /// Frames copied for screenshots since the last call, as RGBA8.
+    fn take_captured_frames(&mut self) -> Vec<HeadlessImage> {
+        Vec::new()
+    }
+
+    /// Stage timings of the most recent frame the GPU finished, if the
+    /// backend measures them and they have not been taken yet.
+    fn take_gpu_timings(&mut self) -> Option<GpuTimings> {
+        None
+    }
+
+    /// Try to get back into a state that can render after the recoverable
+    /// `error`. Called whatever the [`PietErrorPolicy`](crate::PietErrorPolicy),
+    /// and before the frame is retried if the policy says so.
+    fn recover(
+        &mut self,
+        _error: &PietRenderError,
+    ) -> Result<(), PietRenderError> {
+        Ok(())
+    }
+}
+
+/// The backend the render app draws with. Replace this non-send resource of
+/// the render app to plug in a different backend.
+pub struct PietBackend(pub Box<dyn RenderBackend>);
+
+impl Deref for PietBackend {
+    type Target = dyn RenderBackend;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl DerefMut for PietBackend {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.0
+    }
+}
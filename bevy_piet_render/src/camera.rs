@@ -0,0 +1,277 @@
+use bevy::prelude::*;
+use kurbo::{Affine, Point};
+
+use crate::{
+    redraw::RedrawState, PietCoordinateSystem, PietRenderTarget,
+    RenderViewport, RenderWorld,
+};
+
+/// A 2D view onto the scene. The translation of the camera entity is the
+/// world position shown in the center of the viewport, its rotation around
+/// the Z axis turns the view and [`zoom`](Self::zoom) magnifies it.
+///
+/// Only one camera renders into the window, the one with the lowest entity
+/// id if there are several, which also logs a warning. Without any, the
+/// [`PietCoordinateSystem`] alone decides where the world origin is. Cameras with an image [`PietRenderTarget`] render into their
+/// image in addition.
+#[derive(Debug, Component, Clone, Copy)]
+pub struct PietCamera2d {
+    /// Magnification of the view, values above 1.0 zoom in.
+    pub zoom: f32,
+}
+
+impl Default for PietCamera2d {
+    fn default() -> Self {
+        Self { zoom: 1.0 }
+    }
+}
+
+impl PietCamera2d {
+    /// Transform from world coordinates to logical viewport coordinates for
+    /// a viewport of `viewport_size` logical pixels. Viewport coordinates
+    /// start in the top left corner with Y pointing down, whatever the
+    /// coordinate system of the world.
+    pub fn view_affine(
+        &self,
+        transform: &GlobalTransform,
+        coordinate_system: PietCoordinateSystem,
+        viewport_size: Vec2,
+    ) -> Affine {
+        let center = viewport_size / 2.0;
+        let rotation_z = transform.rotation.to_euler(EulerRot::XYZ).2;
+        Affine::translate((center.x as f64, center.y as f64))
+            * coordinate_system.axes()
+            * Affine::scale(self.zoom as f64)
+            * Affine::rotate(-rotation_z as f64)
+            * Affine::translate((
+                -transform.translation.x as f64,
+                -transform.translation.y as f64,
+            ))
+    }
+
+    /// Position in logical viewport pixels of the world position `world`.
+    pub fn world_to_viewport(
+        &self,
+        transform: &GlobalTransform,
+        coordinate_system: PietCoordinateSystem,
+        viewport_size: Vec2,
+        world: Vec2,
+    ) -> Vec2 {
+        let view =
+            self.view_affine(transform, coordinate_system, viewport_size);
+        let p = view * Point::new(world.x as f64, world.y as f64);
+        Vec2::new(p.x as f32, p.y as f32)
+    }
+
+    /// World position shown at `viewport` logical pixels. Note that bevy
+    /// reports cursor positions from the bottom left corner, so flip their Y
+    /// with the viewport height first.
+    pub fn viewport_to_world(
+        &self,
+        transform: &GlobalTransform,
+        coordinate_system: PietCoordinateSystem,
+        viewport_size: Vec2,
+        viewport: Vec2,
+    ) -> Vec2 {
+        let view = self
+            .view_affine(transform, coordinate_system, viewport_size)
+            .inverse();
+        let p = view * Point::new(viewport.x as f64, viewport.y as f64);
+        Vec2::new(p.x as f32, p.y as f32)
+    }
+}
+
+#[derive(Default, Bundle, Clone)]
+pub struct PietCamera2dBundle {
+    pub camera: PietCamera2d,
+    pub target: PietRenderTarget,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
+/// Transform from world coordinates to logical viewport coordinates, from the
+/// active camera or the coordinate system. Applied below the root transform
+/// of every frame.
+pub(crate) struct ExtractedView(pub(crate) Affine);
+
+impl Default for ExtractedView {
+    fn default() -> Self {
+        Self(Affine::IDENTITY)
+    }
+}
+
+pub(crate) fn extract_camera(
+    mut render_world: ResMut<RenderWorld>,
+    cameras: Query<(
+        Entity,
+        &PietCamera2d,
+        &GlobalTransform,
+        Option<&PietRenderTarget>,
+    )>,
+    coordinate_system: Res<PietCoordinateSystem>,
+    mut warned_about_cameras: Local<bool>,
+) {
+    let coordinate_system = *coordinate_system;
+    let viewport_size = render_world
+        .get_resource::<RenderViewport>()
+        .unwrap()
+        .logical_size();
+    // Pick by entity rather than query order, which changes as components
+    // are added and removed.
+    let mut window_cameras = cameras.iter().filter(|(_, _, _, target)| {
+        target.map_or(true, |target| target.is_window())
+    });
+    let mut window_camera = window_cameras.next();
+    for camera in window_cameras {
+        if !*warned_about_cameras {
+            warn!(
+                "Several PietCamera2d render into the window, only the one \
+                 with the lowest entity id is used"
+            );
+            *warned_about_cameras = true;
+        }
+        // `Entity` orders by generation first, compare the ids only.
+        if window_camera
+            .map_or(true, |(entity, ..)| camera.0.id() < entity.id())
+        {
+            window_camera = Some(camera);
+        }
+    }
+    let view = match window_camera {
+        Some((_, camera, transform, _)) => {
+            camera.view_affine(transform, coordinate_system, viewport_size)
+        }
+        None => coordinate_system.world_to_viewport(viewport_size),
+    };
+
+    let mut extracted =
+        render_world.get_resource_mut::<ExtractedView>().unwrap();
+    if extracted.0 == view {
+        return;
+    }
+    extracted.0 = view;
+    render_world
+        .get_resource_mut::<RedrawState>()
+        .unwrap()
+        .request();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn assert_vec2_eq(a: Vec2, b: Vec2) {
+        assert!((a - b).length() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    fn to_viewport(
+        camera: PietCamera2d,
+        transform: GlobalTransform,
+        coordinate_system: PietCoordinateSystem,
+        world: Vec2,
+    ) -> Vec2 {
+        // Logical pixels of an 800 by 600 window.
+        let size = Vec2::new(800.0, 600.0);
+        let viewport = camera.world_to_viewport(
+            &transform,
+            coordinate_system,
+            size,
+            world,
+        );
+        let back = camera.viewport_to_world(
+            &transform,
+            coordinate_system,
+            size,
+            viewport,
+        );
+        assert_vec2_eq(back, world);
+        viewport
+    }
+
+    #[test]
+    fn flips_y_around_the_center() {
+        let to_viewport = |world| {
+            to_viewport(
+                PietCamera2d::default(),
+                GlobalTransform::identity(),
+                PietCoordinateSystem::CenteredYUp,
+                world,
+            )
+        };
+        assert_vec2_eq(to_viewport(Vec2::ZERO), Vec2::new(400.0, 300.0));
+        assert_vec2_eq(
+            to_viewport(Vec2::new(10.0, 20.0)),
+            Vec2::new(410.0, 280.0),
+        );
+    }
+
+    #[test]
+    fn keeps_y_down_in_screen_space() {
+        let viewport = to_viewport(
+            PietCamera2d::default(),
+            GlobalTransform::identity(),
+            PietCoordinateSystem::Screen,
+            Vec2::new(10.0, 20.0),
+        );
+        assert_vec2_eq(viewport, Vec2::new(410.0, 320.0));
+    }
+
+    #[test]
+    fn pans_to_the_camera_translation() {
+        let viewport = to_viewport(
+            PietCamera2d::default(),
+            GlobalTransform::from_xyz(100.0, 50.0, 0.0),
+            PietCoordinateSystem::CenteredYUp,
+            Vec2::new(100.0, 50.0),
+        );
+        assert_vec2_eq(viewport, Vec2::new(400.0, 300.0));
+    }
+
+    #[test]
+    fn zooms_around_the_center() {
+        let viewport = to_viewport(
+            PietCamera2d { zoom: 2.0 },
+            GlobalTransform::identity(),
+            PietCoordinateSystem::CenteredYUp,
+            Vec2::new(10.0, 10.0),
+        );
+        assert_vec2_eq(viewport, Vec2::new(420.0, 280.0));
+    }
+
+    #[test]
+    fn rotates_the_view_against_the_camera() {
+        // Turning the camera a quarter counterclockwise shows the world
+        // X axis pointing down.
+        let viewport = to_viewport(
+            PietCamera2d::default(),
+            GlobalTransform::from_rotation(Quat::from_rotation_z(FRAC_PI_2)),
+            PietCoordinateSystem::CenteredYUp,
+            Vec2::new(10.0, 0.0),
+        );
+        assert_vec2_eq(viewport, Vec2::new(400.0, 310.0));
+    }
+
+    #[test]
+    fn round_trips_pan_zoom_and_rotation() {
+        let transform = GlobalTransform {
+            translation: Vec3::new(-30.0, 75.0, 0.0),
+            rotation: Quat::from_rotation_z(0.7),
+            scale: Vec3::ONE,
+        };
+        for coordinate_system in [
+            PietCoordinateSystem::CenteredYUp,
+            PietCoordinateSystem::Screen,
+        ] {
+            for world in [Vec2::ZERO, Vec2::new(12.5, -40.0)] {
+                to_viewport(
+                    PietCamera2d { zoom: 0.5 },
+                    transform,
+                    coordinate_system,
+                    world,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use bevy::prelude::*;
+use kurbo::Affine;
+
+/// How world coordinates map onto the viewport. Set it on
+/// [`PietRenderPlugin`](crate::PietRenderPlugin). The plugin also inserts it
+/// as a resource, for use with the [`PietCamera2d`](crate::PietCamera2d)
+/// helpers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PietCoordinateSystem {
+    /// Bevy's 2D convention: the origin is in the center of the viewport and
+    /// Y points up. SVGs and text are flipped so they stay upright.
+    CenteredYUp,
+    /// Piet's screen space: the origin is in the top left corner and Y points
+    /// down. Without a camera, world coordinates are logical pixels.
+    Screen,
+}
+
+impl Default for PietCoordinateSystem {
+    fn default() -> Self {
+        PietCoordinateSystem::CenteredYUp
+    }
+}
+
+impl PietCoordinateSystem {
+    /// Orientation of the world axes in the Y-down viewport.
+    pub fn axes(&self) -> Affine {
+        match self {
+            PietCoordinateSystem::CenteredYUp => Affine::FLIP_Y,
+            PietCoordinateSystem::Screen => Affine::IDENTITY,
+        }
+    }
+
+    /// Transform applied to content authored in Y-down coordinates, like
+    /// SVGs and text layouts, so it shows upright in the world.
+    pub fn content_transform(&self) -> Affine {
+        // Flipping is its own inverse.
+        self.axes()
+    }
+
+    /// Transform from world coordinates to logical viewport coordinates when
+    /// no camera is present.
+    pub fn world_to_viewport(&self, viewport_size: Vec2) -> Affine {
+        match self {
+            PietCoordinateSystem::CenteredYUp => {
+                let center = viewport_size / 2.0;
+                Affine::translate((center.x as f64, center.y as f64))
+                    * self.axes()
+            }
+            PietCoordinateSystem::Screen => Affine::IDENTITY,
+        }
+    }
+}
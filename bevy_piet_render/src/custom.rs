@@ -0,0 +1,141 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use bevy::prelude::*;
+use kurbo::{Affine, Rect};
+
+use crate::{
+    backend::RenderBackend, DrawList, PietDrawSystem, PietLayer, PietProducer,
+    PietRenderApp, PietRenderStage, PietVisibility, RenderCommand, RenderType,
+    RenderWorld,
+};
+
+/// Something that draws itself with the [`RenderBackend`], for drawing what
+/// the built-in plugins don't cover, like procedural gauges or debug shapes.
+///
+/// Push it as [`RenderType::Custom`] into the [`DrawList`] from a render app
+/// system labeled [`PietDrawSystem::Queue`], or implement it on a component
+/// and add a [`CustomDrawablePlugin`] for it.
+pub trait CustomDrawable: Send + Sync + 'static {
+    /// Draw into `rc`. Its current transform maps world coordinates to the
+    /// output, and `affine` maps the local coordinates of the entity to world
+    /// coordinates, so call `rc.transform(affine)` first to draw in local
+    /// coordinates. Like those of SVGs and text, local coordinates point Y
+    /// down whatever the [`PietCoordinateSystem`](crate::PietCoordinateSystem),
+    /// so text drawn in them is upright. The state of `rc` is saved and
+    /// restored around this call.
+    fn draw(&self, rc: &mut dyn RenderBackend, affine: Affine);
+
+    /// Bounds of what [`draw`](Self::draw) paints in local coordinates,
+    /// which are transformed by `affine` for viewport culling. `None` means
+    /// the drawable is never culled.
+    fn bounds(&self) -> Option<Rect> {
+        None
+    }
+}
+
+/// Draws every visible entity with a `T` component as a
+/// [`RenderType::Custom`] command, using the entity's `GlobalTransform` and
+/// [`PietLayer`] like the built-in plugins. The component
+/// is cloned into the render world every frame, so keep it cheap to clone.
+pub struct CustomDrawablePlugin<T>(PhantomData<T>);
+
+impl<T> Default for CustomDrawablePlugin<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> Plugin for CustomDrawablePlugin<T>
+where
+    T: CustomDrawable + Component + Clone,
+{
+    fn build(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(PietRenderApp) {
+            render_app
+                .init_resource::<ExtractedCustomDrawables<T>>()
+                .add_system_to_stage(
+                    PietRenderStage::Extract,
+                    extract_custom_drawables::<T>,
+                )
+                .add_system_to_stage(
+                    PietRenderStage::Prepare,
+                    prepare_custom_drawables::<T>.label(PietDrawSystem::Queue),
+                );
+        }
+    }
+}
+
+struct ExtractedCustomDrawable<T> {
+    entity: Entity,
+    drawable: T,
+    transform: GlobalTransform,
+    layer: PietLayer,
+    changed: bool,
+}
+
+struct ExtractedCustomDrawables<T> {
+    drawables: Vec<ExtractedCustomDrawable<T>>,
+}
+
+impl<T> Default for ExtractedCustomDrawables<T> {
+    fn default() -> Self {
+        Self {
+            drawables: Vec::new(),
+        }
+    }
+}
+
+fn extract_custom_drawables<T: CustomDrawable + Component + Clone>(
+    mut render_world: ResMut<RenderWorld>,
+    visibility: PietVisibility,
+    query: Query<(
+        Entity,
+        &T,
+        &GlobalTransform,
+        Option<&PietLayer>,
+        ChangeTrackers<T>,
+        ChangeTrackers<GlobalTransform>,
+    )>,
+) {
+    let mut drawables = Vec::new();
+    for (
+        entity,
+        drawable,
+        transform,
+        layer,
+        drawable_tracker,
+        transform_tracker,
+    ) in query.iter()
+    {
+        if !visibility.is_visible(entity) {
+            continue;
+        }
+        let changed =
+            drawable_tracker.is_changed() || transform_tracker.is_changed();
+        drawables.push(ExtractedCustomDrawable {
+            entity,
+            drawable: drawable.clone(),
+            transform: *transform,
+            layer: layer.copied().unwrap_or(PietLayer::MIDDLE),
+            changed,
+        });
+    }
+    render_world.insert_resource(ExtractedCustomDrawables { drawables });
+}
+
+fn prepare_custom_drawables<T: CustomDrawable + Component + Clone>(
+    mut extracted: ResMut<ExtractedCustomDrawables<T>>,
+    mut draw_list: ResMut<DrawList>,
+) {
+    for extracted in extracted.drawables.drain(..) {
+        let render_type = RenderType::Custom(
+            Arc::new(extracted.drawable),
+            extracted.transform,
+        );
+        draw_list.push(
+            RenderCommand::new(render_type, extracted.layer)
+                .tracked(extracted.entity, extracted.changed)
+                .producer(PietProducer::of::<T>()),
+        );
+    }
+}
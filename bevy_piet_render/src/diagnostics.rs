@@ -0,0 +1,161 @@
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+};
+
+use crate::{PietRenderApp, PietRenderStage, RenderWorld};
+
+/// GPU time spent in each stage of the piet-gpu pipeline for one frame, in
+/// seconds.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GpuTimings {
+    /// Element processing, which flattens the encoded scene.
+    pub element: f64,
+    /// Tile allocation, path coarse rasterization and backdrop computation.
+    pub path: f64,
+    /// Binning of draw objects into bins of tiles.
+    pub binning: f64,
+    /// Coarse rasterization into per tile command lists.
+    pub coarse: f64,
+    /// Fine rasterization of the tiles into the image.
+    pub fine: f64,
+    /// Blit into the swapchain or copy into the readback buffer.
+    pub blit: f64,
+}
+
+impl GpuTimings {
+    /// Split the timestamps returned by `Session::fetch_query_pool`, which
+    /// are relative to the first query, into stage timings.
+    pub(crate) fn from_timestamps(ts: &[f64]) -> Option<Self> {
+        if ts.len() < 8 {
+            return None;
+        }
+        Some(Self {
+            element: ts[0],
+            path: ts[3] - ts[0],
+            binning: ts[4] - ts[3],
+            coarse: ts[5] - ts[4],
+            fine: ts[6] - ts[5],
+            blit: ts[7] - ts[6],
+        })
+    }
+
+    /// GPU time of the whole frame.
+    pub fn total(&self) -> f64 {
+        self.element
+            + self.path
+            + self.binning
+            + self.coarse
+            + self.fine
+            + self.blit
+    }
+}
+
+/// Timings of the last frame finished by the GPU, waiting to be extracted to
+/// the app world.
+#[derive(Default)]
+pub(crate) struct PendingGpuTimings(pub(crate) Option<GpuTimings>);
+
+/// Adds the GPU timings of the piet-gpu pipeline stages as diagnostics, in
+/// milliseconds. Backends that do not measure GPU time, like the CPU
+/// backend, add no measurements.
+#[derive(Default)]
+pub struct PietDiagnosticsPlugin;
+
+impl Plugin for PietDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Diagnostics>()
+            .add_startup_system(Self::setup_system);
+        if let Ok(render_app) = app.get_sub_app_mut(PietRenderApp) {
+            render_app
+                .init_resource::<PendingGpuTimings>()
+                .add_system_to_stage(
+                    PietRenderStage::Extract,
+                    extract_gpu_timings,
+                );
+        }
+    }
+}
+
+impl PietDiagnosticsPlugin {
+    pub const GPU_ELEMENT_TIME: DiagnosticId =
+        DiagnosticId::from_u128(143064823106733447016617352440358934851);
+    pub const GPU_PATH_TIME: DiagnosticId =
+        DiagnosticId::from_u128(228712389462021874911834506745281236904);
+    pub const GPU_BINNING_TIME: DiagnosticId =
+        DiagnosticId::from_u128(30385416574396473926405470713541729402);
+    pub const GPU_COARSE_TIME: DiagnosticId =
+        DiagnosticId::from_u128(281914706148245908183730716524563409715);
+    pub const GPU_FINE_TIME: DiagnosticId =
+        DiagnosticId::from_u128(96810476651453129845436098290742618126);
+    pub const GPU_BLIT_TIME: DiagnosticId =
+        DiagnosticId::from_u128(177263845810297485231573020962158370581);
+    pub const GPU_FRAME_TIME: DiagnosticId =
+        DiagnosticId::from_u128(64207351380623945198260547291037183420);
+
+    pub fn setup_system(mut diagnostics: ResMut<Diagnostics>) {
+        for (id, name) in [
+            (Self::GPU_ELEMENT_TIME, "piet_gpu_element_time"),
+            (Self::GPU_PATH_TIME, "piet_gpu_path_time"),
+            (Self::GPU_BINNING_TIME, "piet_gpu_binning_time"),
+            (Self::GPU_COARSE_TIME, "piet_gpu_coarse_time"),
+            (Self::GPU_FINE_TIME, "piet_gpu_fine_time"),
+            (Self::GPU_BLIT_TIME, "piet_gpu_blit_time"),
+            (Self::GPU_FRAME_TIME, "piet_gpu_frame_time"),
+        ] {
+            diagnostics.add(Diagnostic::new(id, name, 20).with_suffix("ms"));
+        }
+    }
+}
+
+/// Move the GPU timings of the render world into the [`Diagnostics`] of the
+/// app world.
+fn extract_gpu_timings(
+    mut render_world: ResMut<RenderWorld>,
+    mut diagnostics: ResMut<Diagnostics>,
+) {
+    let timings = match render_world
+        .get_resource_mut::<PendingGpuTimings>()
+        .and_then(|mut pending| pending.0.take())
+    {
+        Some(timings) => timings,
+        None => return,
+    };
+
+    for (id, seconds) in [
+        (PietDiagnosticsPlugin::GPU_ELEMENT_TIME, timings.element),
+        (PietDiagnosticsPlugin::GPU_PATH_TIME, timings.path),
+        (PietDiagnosticsPlugin::GPU_BINNING_TIME, timings.binning),
+        (PietDiagnosticsPlugin::GPU_COARSE_TIME, timings.coarse),
+        (PietDiagnosticsPlugin::GPU_FINE_TIME, timings.fine),
+        (PietDiagnosticsPlugin::GPU_BLIT_TIME, timings.blit),
+        (PietDiagnosticsPlugin::GPU_FRAME_TIME, timings.total()),
+    ] {
+        diagnostics.add_measurement(id, seconds * 1e3);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_timestamps_into_stages() {
+        let ts = [1.0, 1.5, 2.0, 3.0, 3.5, 5.0, 8.0, 8.25];
+        let timings = GpuTimings::from_timestamps(&ts).unwrap();
+        assert_eq!(timings.element, 1.0);
+        assert_eq!(timings.path, 2.0);
+        assert_eq!(timings.binning, 0.5);
+        assert_eq!(timings.coarse, 1.5);
+        assert_eq!(timings.fine, 3.0);
+        assert_eq!(timings.blit, 0.25);
+        assert_eq!(timings.total(), 8.25);
+    }
+
+    #[test]
+    fn needs_a_timestamp_per_stage() {
+        assert!(GpuTimings::from_timestamps(&[]).is_none());
+        assert!(GpuTimings::from_timestamps(&[1.0; 7]).is_none());
+        assert!(GpuTimings::from_timestamps(&[1.0; 8]).is_some());
+    }
+}
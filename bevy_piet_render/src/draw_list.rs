@@ -0,0 +1,53 @@
+use bevy::prelude::*;
+
+use crate::RenderCommand;
+
+/// Everything drawn in the current frame, a resource of the render world.
+///
+/// Producers push [`RenderCommand`]s in systems of the
+/// [`Prepare`](crate::PietRenderStage::Prepare) stage labeled
+/// [`PietDrawSystem::Queue`](crate::PietDrawSystem::Queue), and
+/// `prepare_frame` draws the list after all of them. The list is cleared in
+/// the [`Cleanup`](crate::PietRenderStage::Cleanup) stage, so no command is
+/// dropped or carried over into the next frame.
+#[derive(Default)]
+pub struct DrawList {
+    commands: Vec<RenderCommand>,
+}
+
+impl DrawList {
+    pub fn push(&mut self, mut command: RenderCommand) {
+        command.sequence = self.commands.len();
+        self.commands.push(command);
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RenderCommand> {
+        self.commands.iter()
+    }
+
+    pub(crate) fn commands(&self) -> &[RenderCommand] {
+        &self.commands
+    }
+
+    /// Sort the commands into drawing order. The sort keys of all commands
+    /// differ, so the order does not depend on the sort algorithm.
+    pub(crate) fn sort(&mut self) {
+        self.commands.sort_unstable_by(|a, b| a.cmp_sort_key(b));
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+}
+
+pub(crate) fn clear_draw_list(mut draw_list: ResMut<DrawList>) {
+    draw_list.clear();
+}
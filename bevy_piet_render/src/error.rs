@@ -0,0 +1,129 @@
+use std::fmt;
+
+use bevy::prelude::*;
+
+use crate::RenderWorld;
+
+/// Everything that can go wrong while setting up the renderer or rendering a
+/// frame. Sent as an event to the app world and logged through `bevy::log`.
+/// The messages of the underlying piet-gpu-hal errors are kept as strings,
+/// since those errors can neither be cloned nor sent across threads.
+#[derive(Debug, Clone)]
+pub enum PietRenderError {
+    /// The graphics API instance or the window surface could not be created.
+    InstanceCreation(String),
+    /// No suitable device was found, or creating the device or one of the
+    /// resources owned by the renderer failed.
+    DeviceCreation(String),
+    /// The next swapchain image could not be acquired, for example because
+    /// the swapchain is out of date.
+    SwapchainAcquire(String),
+    /// The rendered swapchain image could not be presented.
+    Present(String),
+    /// The scene could not be uploaded to the GPU, for example because it
+    /// does not fit into the renderer buffers.
+    Upload(String),
+    /// Recording, submitting or waiting on a command buffer failed.
+    CommandBuffer(String),
+    /// The backend can't do what was asked, like rendering into an image.
+    Unsupported(String),
+    /// A screenshot could not be encoded or saved.
+    Screenshot(String),
+}
+
+impl PietRenderError {
+    /// Whether retrying the frame after rebuilding the swapchain may succeed.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            PietRenderError::SwapchainAcquire(_) | PietRenderError::Present(_)
+        )
+    }
+}
+
+impl fmt::Display for PietRenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PietRenderError::InstanceCreation(e) => {
+                write!(f, "failed to create Piet instance: {}", e)
+            }
+            PietRenderError::DeviceCreation(e) => {
+                write!(f, "failed to create Piet device: {}", e)
+            }
+            PietRenderError::SwapchainAcquire(e) => {
+                write!(f, "failed to acquire swapchain image: {}", e)
+            }
+            PietRenderError::Present(e) => {
+                write!(f, "failed to present frame: {}", e)
+            }
+            PietRenderError::Upload(e) => {
+                write!(f, "failed to upload scene: {}", e)
+            }
+            PietRenderError::CommandBuffer(e) => {
+                write!(f, "command buffer failure: {}", e)
+            }
+            PietRenderError::Unsupported(e) => {
+                write!(f, "not supported by the render backend: {}", e)
+            }
+            PietRenderError::Screenshot(e) => {
+                write!(f, "failed to save screenshot: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PietRenderError {}
+
+/// What the renderer does when rendering a frame fails.
+#[derive(Debug, Clone, Copy)]
+pub enum PietErrorPolicy {
+    /// Drop the failed frame and carry on with the next one. The swapchain is
+    /// still rebuilt after a recoverable error.
+    SkipFrame,
+    /// Rebuild the swapchain and render the frame again up to `max_attempts`
+    /// times if the error is recoverable, then skip it.
+    Retry { max_attempts: u32 },
+    /// Panic on the first error.
+    Panic,
+}
+
+impl Default for PietErrorPolicy {
+    fn default() -> Self {
+        PietErrorPolicy::SkipFrame
+    }
+}
+
+/// Errors raised in the render world that have not been sent to the app
+/// world yet.
+#[derive(Default)]
+pub(crate) struct PendingRenderErrors(Vec<PietRenderError>);
+
+impl PendingRenderErrors {
+    /// Log `error` and queue it for the app world, or panic if the policy
+    /// says so.
+    pub(crate) fn report(
+        &mut self,
+        error: PietRenderError,
+        policy: PietErrorPolicy,
+    ) {
+        if let PietErrorPolicy::Panic = policy {
+            panic!("Error: {}", error);
+        }
+        error!("{}", error);
+        self.0.push(error);
+    }
+}
+
+/// Send the errors raised in the render world since the last extraction as
+/// [`PietRenderError`] events of the app world.
+pub(crate) fn extract_render_errors(
+    mut render_world: ResMut<RenderWorld>,
+    mut events: EventWriter<PietRenderError>,
+) {
+    let mut pending = render_world
+        .get_resource_mut::<PendingRenderErrors>()
+        .unwrap();
+    for error in pending.0.drain(..) {
+        events.send(error);
+    }
+}
@@ -0,0 +1,539 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    ecs::{
+        query::{FilterFetch, QueryItem, WorldQuery},
+        system::{StaticSystemParam, SystemParam},
+    },
+    prelude::*,
+    utils::HashMap,
+};
+
+use crate::{
+    PietLayer, PietRenderApp, PietRenderStage, PietVisibility, RenderWorld,
+};
+
+/// A component that an [`ExtractComponentPlugin`] creates from app world
+/// data every frame, and inserts on the render world entity with the same id.
+///
+/// Render world entities are cleared at the end of every frame, so a system
+/// in the [`Prepare`](PietRenderStage::Prepare) stage labeled
+/// [`PietDrawSystem::Queue`](crate::PietDrawSystem::Queue) can query the
+/// extracted components and push them into the [`DrawList`](crate::DrawList)
+/// without keeping any state of its own.
+pub trait ExtractComponent: Component {
+    /// The app world data the component is created from.
+    type Query: WorldQuery;
+    /// Which app world entities are extracted, `()` for all of them.
+    type Filter: WorldQuery;
+
+    fn extract_component(item: QueryItem<Self::Query>) -> Self;
+}
+
+/// Extracts a [`ExtractComponent`] into the render world in the
+/// [`Extract`](PietRenderStage::Extract) stage of every frame.
+pub struct ExtractComponentPlugin<C> {
+    only_visible: bool,
+    marker: PhantomData<fn() -> C>,
+}
+
+impl<C> Default for ExtractComponentPlugin<C> {
+    fn default() -> Self {
+        Self {
+            only_visible: false,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<C> ExtractComponentPlugin<C> {
+    /// Skip entities that are hidden by their own or an ancestor's
+    /// `Visibility`, see [`PietVisibility`].
+    pub fn extract_visible() -> Self {
+        Self {
+            only_visible: true,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<C: ExtractComponent> Plugin for ExtractComponentPlugin<C>
+where
+    <C::Filter as WorldQuery>::Fetch: FilterFetch,
+{
+    fn build(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(PietRenderApp) {
+            if self.only_visible {
+                render_app.add_system_to_stage(
+                    PietRenderStage::Extract,
+                    extract_visible_components::<C>,
+                );
+            } else {
+                render_app.add_system_to_stage(
+                    PietRenderStage::Extract,
+                    extract_components::<C>,
+                );
+            }
+        }
+    }
+}
+
+fn extract_components<C: ExtractComponent>(
+    mut commands: Commands,
+    mut previous_len: Local<usize>,
+    mut query: StaticSystemParam<Query<(Entity, C::Query), C::Filter>>,
+) where
+    <C::Filter as WorldQuery>::Fetch: FilterFetch,
+{
+    let mut values = Vec::with_capacity(*previous_len);
+    for (entity, item) in query.iter_mut() {
+        values.push((entity, (C::extract_component(item),)));
+    }
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
+}
+
+fn extract_visible_components<C: ExtractComponent>(
+    mut commands: Commands,
+    mut previous_len: Local<usize>,
+    visibility: PietVisibility,
+    mut query: StaticSystemParam<Query<(Entity, C::Query), C::Filter>>,
+) where
+    <C::Filter as WorldQuery>::Fetch: FilterFetch,
+{
+    let mut values = Vec::with_capacity(*previous_len);
+    for (entity, item) in query.iter_mut() {
+        if visibility.is_visible(entity) {
+            values.push((entity, (C::extract_component(item),)));
+        }
+    }
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
+}
+
+/// The render world copy of an app world entity, kept across frames in
+/// [`ExtractedEntities`].
+pub struct ExtractedEntity<T> {
+    pub data: T,
+    /// The [`PietLayer`] of the entity, `None` without one.
+    pub layer: Option<PietLayer>,
+    /// Whether the entity is visible, see [`PietVisibility`].
+    pub visible: bool,
+    /// Whether the entity changed since it was last drawn, which makes the
+    /// renderer draw a new frame in reactive mode. Reset it when drawing.
+    pub changed: bool,
+}
+
+/// Render world state of app world entities, kept across frames so extract
+/// systems only have to update the entities that changed.
+///
+/// Extract systems [`insert`](Self::insert) or [`update`](Self::update)
+/// the data of changed entities, and then call
+/// [`ExtractedEntitiesSync::sync`], which drops despawned entities and keeps
+/// layers and visibility up to date.
+pub struct ExtractedEntities<T> {
+    entities: HashMap<Entity, ExtractedEntity<T>>,
+    /// Entities inserted since the last sync, without a layer or visibility
+    /// yet.
+    added: Vec<Entity>,
+}
+
+impl<T> Default for ExtractedEntities<T> {
+    fn default() -> Self {
+        Self {
+            entities: HashMap::default(),
+            added: Vec::new(),
+        }
+    }
+}
+
+impl<T> ExtractedEntities<T> {
+    /// Set the data of `entity` and mark it as changed.
+    pub fn insert(&mut self, entity: Entity, data: T) {
+        match self.entities.get_mut(&entity) {
+            Some(extracted) => {
+                extracted.data = data;
+                extracted.changed = true;
+            }
+            None => {
+                self.entities.insert(
+                    entity,
+                    ExtractedEntity {
+                        data,
+                        layer: None,
+                        visible: true,
+                        changed: true,
+                    },
+                );
+                self.added.push(entity);
+            }
+        }
+    }
+
+    /// The data of `entity` to update in place, which marks it as changed.
+    /// `None` if the entity was never inserted.
+    pub fn update(&mut self, entity: Entity) -> Option<&mut T> {
+        let extracted = self.entities.get_mut(&entity)?;
+        extracted.changed = true;
+        Some(&mut extracted.data)
+    }
+
+    pub fn iter_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (Entity, &mut ExtractedEntity<T>)> {
+        self.entities
+            .iter_mut()
+            .map(|(entity, extracted)| (*entity, extracted))
+    }
+}
+
+type WithLayer<F> = (F, With<PietLayer>);
+type LayerChanged<F> = (F, Changed<PietLayer>);
+
+/// Keeps [`ExtractedEntities`] in sync with the app world entities matching
+/// the filter `F`, like `(With<MyDrawable>, With<GlobalTransform>)`, in
+/// extract systems.
+///
+/// `RemovedComponents` is already cleared when extract systems run, so
+/// removals are found by counting instead: every entity matching `F` was
+/// inserted when it was added, so as long as the counts are equal nothing
+/// was removed, and only otherwise the extracted entities are swept.
+#[derive(SystemParam)]
+pub struct ExtractedEntitiesSync<'w, 's, F: WorldQuery + 'static>
+where
+    F::Fetch: FilterFetch,
+{
+    matching: Query<'w, 's, (), F>,
+    with_layer: Query<'w, 's, (), WithLayer<F>>,
+    changed_layers:
+        Query<'w, 's, (Entity, &'static PietLayer), LayerChanged<F>>,
+    layers: Query<'w, 's, Option<&'static PietLayer>>,
+    visibility: PietVisibility<'w, 's>,
+}
+
+impl<'w, 's, F: WorldQuery + 'static> ExtractedEntitiesSync<'w, 's, F>
+where
+    F::Fetch: FilterFetch,
+{
+    /// Drop the entities that no longer match `F`, and update the layer and
+    /// visibility of the others. Call it once per run, after inserting and
+    /// updating the changed entities.
+    pub fn sync<T>(&mut self, extracted: &mut ExtractedEntities<T>) {
+        let entities = &mut extracted.entities;
+        if entities.len() != self.matching.iter().count() {
+            let matching = &self.matching;
+            entities.retain(|entity, _| matching.get(*entity).is_ok());
+        }
+
+        for entity in extracted.added.drain(..) {
+            if let Some(extracted) = entities.get_mut(&entity) {
+                extracted.layer =
+                    self.layers.get(entity).ok().flatten().copied();
+                extracted.visible = self.visibility.is_visible(entity);
+            }
+        }
+
+        // Entities with a layer were updated when it was added or changed,
+        // so only fewer layers than before mean that some were removed.
+        for (entity, layer) in self.changed_layers.iter() {
+            if let Some(extracted) = entities.get_mut(&entity) {
+                extracted.layer = Some(*layer);
+                extracted.changed = true;
+            }
+        }
+        let layered = entities.values().filter(|e| e.layer.is_some()).count();
+        if layered != self.with_layer.iter().count() {
+            for (entity, extracted) in entities.iter_mut() {
+                if extracted.layer.is_some()
+                    && self.with_layer.get(*entity).is_err()
+                {
+                    extracted.layer = None;
+                    extracted.changed = true;
+                }
+            }
+        }
+
+        if self.visibility.hierarchy_changed() {
+            for (entity, extracted) in entities.iter_mut() {
+                let visible = self.visibility.is_visible(*entity);
+                // Showing or hiding the entity changes the frame.
+                extracted.changed |= visible != extracted.visible;
+                extracted.visible = visible;
+            }
+        }
+    }
+}
+
+/// A render world resource that an [`ExtractResourcePlugin`] creates from an
+/// app world resource. Unlike entities, render world resources are kept
+/// across frames, so it is only extracted again when the source changed.
+pub trait ExtractResource: Send + Sync + 'static {
+    /// The app world resource it is created from.
+    type Source: Send + Sync + 'static;
+
+    fn extract_resource(source: &Self::Source) -> Self;
+}
+
+/// Extracts a [`ExtractResource`] into the render world in the
+/// [`Extract`](PietRenderStage::Extract) stage, whenever its source was
+/// added or changed.
+pub struct ExtractResourcePlugin<R>(PhantomData<fn() -> R>);
+
+impl<R> Default for ExtractResourcePlugin<R> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<R: ExtractResource> Plugin for ExtractResourcePlugin<R> {
+    fn build(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(PietRenderApp) {
+            render_app.add_system_to_stage(
+                PietRenderStage::Extract,
+                extract_resource::<R>,
+            );
+        }
+    }
+}
+
+pub(crate) fn extract_resource<R: ExtractResource>(
+    mut render_world: ResMut<RenderWorld>,
+    source: Option<Res<R::Source>>,
+) {
+    if let Some(source) = source {
+        if source.is_changed() {
+            render_world.insert_resource(R::extract_resource(&source));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::AssetPlugin;
+
+    use super::*;
+    use crate::{PietRenderMode, PietRenderPlugin};
+
+    #[derive(Component)]
+    struct Source(u32);
+
+    #[derive(Component)]
+    struct Extracted(u32);
+
+    impl ExtractComponent for Extracted {
+        type Query = &'static Source;
+        type Filter = ();
+
+        fn extract_component(source: QueryItem<Self::Query>) -> Self {
+            Extracted(source.0)
+        }
+    }
+
+    /// What the render world held during the last frame.
+    #[derive(Default)]
+    struct SeenInRenderWorld(Vec<u32>);
+
+    fn record_extracted(
+        query: Query<&Extracted>,
+        mut seen: ResMut<SeenInRenderWorld>,
+    ) {
+        seen.0 = query.iter().map(|extracted| extracted.0).collect();
+    }
+
+    /// An app that renders on the CPU.
+    fn cpu_app() -> App {
+        let mut app = App::new();
+        app.insert_resource(PietRenderMode::Cpu {
+            width: 16,
+            height: 16,
+        })
+        .add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin)
+        .add_plugin(PietRenderPlugin::default());
+        app
+    }
+
+    /// An app that renders on the CPU, with the render world entities
+    /// holding an [`Extracted`] recorded into [`SeenInRenderWorld`].
+    fn app(plugin: ExtractComponentPlugin<Extracted>) -> App {
+        let mut app = cpu_app();
+        app.add_plugin(plugin);
+        app.sub_app_mut(PietRenderApp)
+            .init_resource::<SeenInRenderWorld>()
+            .add_system_to_stage(PietRenderStage::Prepare, record_extracted);
+        app
+    }
+
+    fn seen_in_render_world(app: &App) -> Vec<u32> {
+        let seen = app
+            .sub_app(PietRenderApp)
+            .world
+            .resource::<SeenInRenderWorld>();
+        let mut seen = seen.0.clone();
+        seen.sort_unstable();
+        seen
+    }
+
+    #[test]
+    fn extracts_into_the_render_world_only() {
+        let mut app = app(ExtractComponentPlugin::default());
+        app.world.spawn().insert(Source(7));
+
+        app.update();
+
+        assert_eq!(seen_in_render_world(&app), vec![7]);
+        let mut extracted = app.world.query::<&Extracted>();
+        assert_eq!(extracted.iter(&app.world).count(), 0);
+    }
+
+    #[cfg(feature = "bevy_render")]
+    #[test]
+    fn hiding_a_parent_hides_its_children() {
+        let mut app = app(ExtractComponentPlugin::extract_visible());
+        let parent = app
+            .world
+            .spawn()
+            .insert_bundle((Source(1), Visibility { is_visible: true }))
+            .id();
+        let child = app.world.spawn().insert(Source(2)).id();
+        app.world.entity_mut(parent).push_children(&[child]);
+        app.world.spawn().insert(Source(3));
+
+        app.update();
+        assert_eq!(seen_in_render_world(&app), vec![1, 2, 3]);
+
+        app.world.get_mut::<Visibility>(parent).unwrap().is_visible = false;
+        app.update();
+        assert_eq!(seen_in_render_world(&app), vec![3]);
+
+        app.world.entity_mut(parent).remove::<Visibility>();
+        app.update();
+        assert_eq!(seen_in_render_world(&app), vec![1, 2, 3]);
+    }
+
+    #[derive(Component)]
+    struct Drawable(u32);
+
+    type Drawables = ExtractedEntities<u32>;
+
+    fn extract_drawables(
+        changed: Query<(Entity, &Drawable), Changed<Drawable>>,
+        mut entities: ExtractedEntitiesSync<(
+            With<Drawable>,
+            With<GlobalTransform>,
+        )>,
+        mut render_world: ResMut<RenderWorld>,
+    ) {
+        let mut drawables = render_world.resource_mut::<Drawables>();
+        for (entity, drawable) in changed.iter() {
+            drawables.insert(entity, drawable.0);
+        }
+        entities.sync(&mut drawables);
+    }
+
+    /// The visible drawables of the last frame and their layers.
+    #[derive(Default)]
+    struct SeenDrawables(Vec<(u32, Option<i32>)>);
+
+    fn record_drawables(
+        mut drawables: ResMut<Drawables>,
+        mut seen: ResMut<SeenDrawables>,
+    ) {
+        seen.0 = drawables
+            .iter_mut()
+            .filter(|(_, drawable)| drawable.visible)
+            .map(|(_, drawable)| {
+                (drawable.data, drawable.layer.map(|layer| layer.0))
+            })
+            .collect();
+        seen.0.sort_unstable();
+    }
+
+    fn drawables_app() -> App {
+        let mut app = cpu_app();
+        app.sub_app_mut(PietRenderApp)
+            .init_resource::<Drawables>()
+            .init_resource::<SeenDrawables>()
+            .add_system_to_stage(PietRenderStage::Extract, extract_drawables)
+            .add_system_to_stage(PietRenderStage::Prepare, record_drawables);
+        app
+    }
+
+    fn seen_drawables(app: &App) -> Vec<(u32, Option<i32>)> {
+        let world = &app.sub_app(PietRenderApp).world;
+        world.resource::<SeenDrawables>().0.clone()
+    }
+
+    #[test]
+    fn drops_despawned_and_removed_entities() {
+        let mut app = drawables_app();
+        let despawned = app
+            .world
+            .spawn()
+            .insert_bundle((Drawable(1), GlobalTransform::identity()))
+            .id();
+        let layered = app
+            .world
+            .spawn()
+            .insert_bundle((
+                Drawable(2),
+                GlobalTransform::identity(),
+                PietLayer(3),
+            ))
+            .id();
+        let removed = app
+            .world
+            .spawn()
+            .insert_bundle((Drawable(3), GlobalTransform::identity()))
+            .id();
+
+        app.update();
+        assert_eq!(
+            seen_drawables(&app),
+            vec![(1, None), (2, Some(3)), (3, None)]
+        );
+
+        app.world.despawn(despawned);
+        app.world.entity_mut(removed).remove::<Drawable>();
+        app.world.entity_mut(layered).remove::<PietLayer>();
+        app.update();
+        assert_eq!(seen_drawables(&app), vec![(2, None)]);
+
+        // Equal counts of matching and extracted entities after a despawn
+        // and a spawn in the same frame.
+        app.world.despawn(layered);
+        app.world
+            .spawn()
+            .insert_bundle((Drawable(4), GlobalTransform::identity()));
+        app.update();
+        assert_eq!(seen_drawables(&app), vec![(4, None)]);
+    }
+
+    #[cfg(feature = "bevy_render")]
+    #[test]
+    fn hides_extracted_children_of_hidden_parents() {
+        let mut app = drawables_app();
+        let parent = app
+            .world
+            .spawn()
+            .insert_bundle((Visibility::default(), GlobalTransform::identity()))
+            .id();
+        let child = app
+            .world
+            .spawn()
+            .insert_bundle((Drawable(1), GlobalTransform::identity()))
+            .id();
+        app.world.entity_mut(parent).push_children(&[child]);
+
+        app.update();
+        assert_eq!(seen_drawables(&app), vec![(1, None)]);
+
+        app.world.get_mut::<Visibility>(parent).unwrap().is_visible = false;
+        app.update();
+        assert_eq!(seen_drawables(&app), vec![]);
+
+        app.world.entity_mut(parent).remove::<Visibility>();
+        app.update();
+        assert_eq!(seen_drawables(&app), vec![(1, None)]);
+    }
+}
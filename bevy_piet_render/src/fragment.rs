@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use kurbo::{Affine, BezPath, Point};
+use piet_gpu::Color;
+
+use crate::{
+    backend::RenderBackend, PietRenderError, PietRenderSettings, RenderViewport,
+};
+
+/// A single recorded drawing call of a [`SceneFragment`].
+#[derive(Clone)]
+pub enum DrawOp {
+    Save,
+    Restore,
+    Transform(Affine),
+    Fill(BezPath, Color),
+    Stroke(BezPath, Color, f64),
+    Text(String, f64, Point),
+    /// A fragment shared with other fragments, see
+    /// [`RenderBackend::draw_fragment`].
+    Fragment(Arc<SceneFragment>),
+}
+
+/// A recorded piece of a scene that can be replayed into any
+/// [`RenderBackend`] later on, as often as needed.
+///
+/// Piet-gpu at the pinned revision can't append pre-encoded scene data, so
+/// fragments store the drawing calls themselves. Recording happens by drawing
+/// into the fragment, which implements [`RenderBackend`].
+#[derive(Clone, Default)]
+pub struct SceneFragment {
+    ops: Vec<DrawOp>,
+}
+
+impl SceneFragment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ops(&self) -> &[DrawOp] {
+        &self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+
+    /// Issue all recorded drawing calls to `rc`, on top of its current
+    /// transform.
+    pub fn replay<R: RenderBackend + ?Sized>(&self, rc: &mut R) {
+        for op in &self.ops {
+            match op {
+                DrawOp::Save => rc.save(),
+                DrawOp::Restore => rc.restore(),
+                DrawOp::Transform(affine) => rc.transform(*affine),
+                DrawOp::Fill(path, color) => rc.fill(path, color),
+                DrawOp::Stroke(path, color, width) => {
+                    rc.stroke(path, color, *width)
+                }
+                DrawOp::Text(text, font_size, origin) => {
+                    rc.draw_text(text, *font_size, *origin)
+                }
+                DrawOp::Fragment(fragment) => rc.draw_fragment(fragment),
+            }
+        }
+    }
+}
+
+impl FromIterator<DrawOp> for SceneFragment {
+    fn from_iter<I: IntoIterator<Item = DrawOp>>(ops: I) -> Self {
+        Self {
+            ops: ops.into_iter().collect(),
+        }
+    }
+}
+
+impl RenderBackend for SceneFragment {
+    fn begin_frame(&mut self) {
+        self.clear();
+    }
+
+    fn save(&mut self) {
+        self.ops.push(DrawOp::Save);
+    }
+
+    fn restore(&mut self) {
+        self.ops.push(DrawOp::Restore);
+    }
+
+    fn transform(&mut self, transform: Affine) {
+        self.ops.push(DrawOp::Transform(transform));
+    }
+
+    fn fill(&mut self, path: &BezPath, color: &Color) {
+        self.ops.push(DrawOp::Fill(path.clone(), color.clone()));
+    }
+
+    fn stroke(&mut self, path: &BezPath, color: &Color, width: f64) {
+        self.ops
+            .push(DrawOp::Stroke(path.clone(), color.clone(), width));
+    }
+
+    fn draw_text(&mut self, text: &str, font_size: f64, origin: Point) {
+        self.ops
+            .push(DrawOp::Text(text.to_string(), font_size, origin));
+    }
+
+    fn draw_fragment(&mut self, fragment: &Arc<SceneFragment>) {
+        self.ops.push(DrawOp::Fragment(fragment.clone()));
+    }
+
+    fn end_frame(&mut self, _frame: usize) -> Result<(), PietRenderError> {
+        Ok(())
+    }
+
+    fn resize(
+        &mut self,
+        _viewport: &RenderViewport,
+    ) -> Result<(), PietRenderError> {
+        Ok(())
+    }
+
+    fn configure(
+        &mut self,
+        _settings: &PietRenderSettings,
+    ) -> Result<(), PietRenderError> {
+        Ok(())
+    }
+}
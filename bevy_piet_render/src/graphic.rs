@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use kurbo::{BezPath, Rect, Shape};
+use piet_gpu::Color;
+
+use crate::{backend::RenderBackend, DrawOp, SceneFragment};
+
+/// A single path of a [`VectorGraphic`] and how it is painted.
+#[derive(Clone)]
+pub enum PathItem {
+    Fill {
+        path: BezPath,
+        color: Color,
+    },
+    Stroke {
+        path: BezPath,
+        color: Color,
+        width: f64,
+    },
+}
+
+impl PathItem {
+    /// Bounding box of the painted area. Strokes are padded by their full
+    /// width, which also covers miter joins of moderate angles.
+    pub fn bounding_box(&self) -> Rect {
+        match self {
+            PathItem::Fill { path, .. } => path.bounding_box(),
+            PathItem::Stroke { path, width, .. } => {
+                path.bounding_box().inflate(*width, *width)
+            }
+        }
+    }
+}
+
+/// Vector graphic made of filled and stroked paths. Unlike piet-gpu's
+/// `PicoSvg` its paths are accessible, so every [`RenderBackend`] can draw it.
+///
+/// The paths are kept as a fragment, which every draw of the graphic shares,
+/// so drawing it many times copies no paths. Each draw still encodes the
+/// paths again, the pinned piet-gpu can't reuse an encoded fragment.
+#[derive(Clone, Default)]
+pub struct VectorGraphic {
+    /// The drawing calls of all paths.
+    fragment: Arc<SceneFragment>,
+    /// Union of the bounding boxes of all paths, computed once.
+    bounds: Option<Rect>,
+}
+
+impl VectorGraphic {
+    pub fn new(items: Vec<PathItem>) -> Self {
+        let bounds = items
+            .iter()
+            .map(PathItem::bounding_box)
+            .reduce(|a, b| a.union(b));
+        let fragment = items
+            .into_iter()
+            .map(|item| match item {
+                PathItem::Fill { path, color } => DrawOp::Fill(path, color),
+                PathItem::Stroke { path, color, width } => {
+                    DrawOp::Stroke(path, color, width)
+                }
+            })
+            .collect();
+        Self {
+            fragment: Arc::new(fragment),
+            bounds,
+        }
+    }
+
+    /// Bounding box of everything the graphic paints in its own coordinates,
+    /// `None` if it has no paths.
+    pub fn bounds(&self) -> Option<Rect> {
+        self.bounds
+    }
+
+    /// The shared fragment drawing all paths, one fill or stroke per path.
+    pub fn fragment(&self) -> &Arc<SceneFragment> {
+        &self.fragment
+    }
+
+    /// Draw all paths in order with the current transform of the backend.
+    pub fn render(&self, backend: &mut dyn RenderBackend) {
+        backend.draw_fragment(&self.fragment);
+    }
+}
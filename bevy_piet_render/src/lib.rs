@@ -1,20 +1,78 @@
 use std::ops::{Deref, DerefMut};
 
-mod math;
+mod backend;
+mod camera;
+mod coordinates;
+mod custom;
+mod diagnostics;
+mod draw_list;
+mod error;
+mod extract;
+mod fragment;
+mod graphic;
+pub mod math;
+mod painter;
+mod redraw;
 mod render;
+mod screenshot;
+mod settings;
+mod target;
+mod visibility;
 
 use bevy::{
     app::{App, AppLabel, Plugin},
-    ecs::{event::Events, schedule::RunOnce},
     prelude::*,
+    window::RequestRedraw,
 };
-use render::{prepare_frame, render_frame, setup_piet_renderer};
+use camera::{extract_camera, ExtractedView};
+use draw_list::clear_draw_list;
+use error::extract_render_errors;
+use extract::extract_resource;
+use painter::{
+    extract_painter, prepare_painter, ExtractedPainter, PainterQueue,
+};
+use redraw::{extract_redraw_events, RedrawState};
+use render::{
+    apply_render_settings, extract_window_viewport, limit_frame_rate,
+    prepare_frame, render_frame, resize_renderer, setup_piet_renderer,
+    RenderSystem,
+};
+use screenshot::{
+    collect_screenshots, extract_screenshots, request_screenshots,
+    FinishedScreenshots, PendingScreenshots,
+};
+#[cfg(feature = "bevy_render")]
+use target::{extract_image_targets, extract_rendered_images};
+use target::{ExtractedImageTargets, RenderedImages};
 
 /// A Label for the rendering sub-app.
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, AppLabel)]
 pub struct PietRenderApp;
 
-pub use render::{RenderCommand, RenderLayer, RenderType};
+pub use render::{
+    HeadlessFrame, HeadlessImage, PietLayer, PietProducer, PietRenderMode,
+    RenderCommand, RenderType, RenderViewport,
+};
+pub use backend::{CpuBackend, GpuBackend, PietBackend, RenderBackend};
+pub use camera::{PietCamera2d, PietCamera2dBundle};
+pub use coordinates::PietCoordinateSystem;
+pub use custom::{CustomDrawable, CustomDrawablePlugin};
+pub use diagnostics::{GpuTimings, PietDiagnosticsPlugin};
+pub use draw_list::DrawList;
+pub use error::{PietErrorPolicy, PietRenderError};
+pub use extract::{
+    ExtractComponent, ExtractComponentPlugin, ExtractResource,
+    ExtractResourcePlugin, ExtractedEntities, ExtractedEntitiesSync,
+    ExtractedEntity,
+};
+pub use fragment::{DrawOp, SceneFragment};
+pub use graphic::{PathItem, VectorGraphic};
+pub use painter::{PainterState, PietPainter};
+pub use piet_gpu::Color as PietColor;
+pub use screenshot::{PietScreenshotTaken, TakePietScreenshot};
+pub use settings::{PietRenderSettings, PietUpdateMode};
+pub use target::PietRenderTarget;
+pub use visibility::PietVisibility;
 
 /// The Render App World. This is only available as a resource during the
 /// Extract step.
@@ -42,11 +100,15 @@ struct ScratchRenderWorld(World);
 
 /// Contains the Bevy interface to the Piet renderer.
 #[derive(Default)]
-pub struct PietRenderPlugin;
+pub struct PietRenderPlugin {
+    /// How world coordinates map onto the viewport.
+    pub coordinate_system: PietCoordinateSystem,
+}
 
 /// The labels of the default App rendering stages.
 #[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
 pub enum PietRenderStage {
+    /// Runs before anything of the frame is extracted.
     Setup,
 
     /// Extract data from the "app world" and insert it into the "render
@@ -66,33 +128,122 @@ pub enum PietRenderStage {
     Cleanup,
 }
 
+/// Labels ordering the systems around the [`DrawList`] in the
+/// [`Prepare`](PietRenderStage::Prepare) stage.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub enum PietDrawSystem {
+    /// Systems pushing [`RenderCommand`]s into the [`DrawList`]. Label your
+    /// own producers with it.
+    Queue,
+    /// Draws the [`DrawList`] into the backend, after every
+    /// [`Queue`](Self::Queue) system.
+    Draw,
+}
+
 impl Plugin for PietRenderPlugin {
     /// Initializes the renderer, sets up the
     /// [`PietRenderStage`](PietRenderStage) and creates the rendering sub-app.
     fn build(&self, app: &mut App) {
-        app.init_resource::<ScratchRenderWorld>();
+        app.init_resource::<ScratchRenderWorld>()
+            .init_resource::<PietRenderSettings>()
+            .insert_resource(self.coordinate_system)
+            .add_event::<PietRenderError>()
+            .add_event::<RequestRedraw>()
+            .init_resource::<PainterQueue>()
+            .add_event::<TakePietScreenshot>()
+            .add_event::<PietScreenshotTaken>()
+            .init_resource::<FinishedScreenshots>();
+        // Image targets need the `Image` assets, which bevy's own render
+        // plugin would register otherwise.
+        #[cfg(feature = "bevy_render")]
+        if !app.world.contains_resource::<Assets<Image>>() {
+            app.add_asset::<Image>();
+        }
 
         let mut render_app = App::empty();
 
+        let mut extract_stage =
+            SystemStage::parallel().with_system(extract_redraw_events);
+        // don't apply buffers when the stage finishes running
+        // extract stage runs on the app world, but the buffers are applied to
+        // the render world by `extract`
+        extract_stage.set_apply_buffers(false);
+
         render_app
         .add_stage(
                 PietRenderStage::Setup,
-                SystemStage::parallel().with_run_criteria(RunOnce::default()), // .with_system(setup_piet.exclusive_system().at_start()),
+                SystemStage::parallel().with_system(apply_render_settings),
             )
             .add_stage(
                 PietRenderStage::Extract,
-                SystemStage::parallel(), // .with_system(extract_redraw_events),
+                extract_stage,
             )
             .add_stage(
                 PietRenderStage::Prepare,
-                SystemStage::parallel().with_system(prepare_frame), // .with_system(Events::<RenderFrameEvent>::update_system),
+                SystemStage::parallel().with_system(
+                    prepare_frame
+                        .label(PietDrawSystem::Draw)
+                        .after(PietDrawSystem::Queue),
+                ),
             )
             .add_stage(PietRenderStage::Render, SystemStage::single(render_frame))
-            .add_stage(PietRenderStage::Cleanup, SystemStage::parallel())
-            .init_resource::<Events<RenderCommand>>()
-            .add_system_to_stage(PietRenderStage::Prepare, Events::<RenderCommand>::update_system);
+            .add_stage(
+                PietRenderStage::Cleanup,
+                SystemStage::parallel()
+                    .with_system(limit_frame_rate)
+                    .with_system(clear_draw_list),
+            )
+            .init_resource::<DrawList>()
+            .add_system_to_stage(PietRenderStage::Extract, extract_render_errors)
+            .add_system_to_stage(
+                PietRenderStage::Extract,
+                extract_resource::<PietRenderSettings>,
+            )
+            .init_resource::<ExtractedView>()
+            .init_resource::<RedrawState>()
+            .init_resource::<ExtractedPainter>()
+            .add_system_to_stage(PietRenderStage::Extract, extract_painter)
+            .add_system_to_stage(
+                PietRenderStage::Prepare,
+                prepare_painter.label(PietDrawSystem::Queue),
+            )
+            .insert_resource(self.coordinate_system)
+            .add_system_to_stage(
+                PietRenderStage::Extract,
+                extract_camera.label(RenderSystem::ExtractCamera),
+            )
+            .init_resource::<ExtractedImageTargets>()
+            .init_resource::<RenderedImages>()
+            .init_resource::<PendingScreenshots>()
+            .add_system_to_stage(PietRenderStage::Extract, extract_screenshots)
+            .add_system_to_stage(PietRenderStage::Prepare, request_screenshots)
+            .add_system_to_stage(PietRenderStage::Cleanup, collect_screenshots)
+            .add_system_to_stage(
+                PietRenderStage::Prepare,
+                resize_renderer.before(PietDrawSystem::Draw),
+            );
+        #[cfg(feature = "bevy_render")]
+        render_app
+            .add_system_to_stage(PietRenderStage::Extract, extract_image_targets)
+            .add_system_to_stage(
+                PietRenderStage::Extract,
+                extract_rendered_images,
+            );
+
+        let mode = app
+            .world
+            .get_resource::<PietRenderMode>()
+            .copied()
+            .unwrap_or_default();
+        if let PietRenderMode::Windowed = mode {
+            // The camera view depends on the size of the viewport.
+            render_app.add_system_to_stage(
+                PietRenderStage::Extract,
+                extract_window_viewport.before(RenderSystem::ExtractCamera),
+            );
+        }
 
-        setup_piet_renderer(&app.world, &mut render_app);
+        setup_piet_renderer(&mut app.world, &mut render_app);
 
         app.add_sub_app(
             PietRenderApp,
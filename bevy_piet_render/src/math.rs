@@ -1,16 +1,199 @@
-use bevy::prelude::*;
+//! Conversions between bevy's transform types and [`kurbo::Affine`].
+//!
+//! A kurbo affine `[a, b, c, d, e, f]` maps `(x, y)` to
+//! `(a * x + c * y + e, b * x + d * y + f)`, which is the column major layout
+//! bevy uses for [`Affine2`] and [`Mat3`] as well.
+
+use bevy::{math::Affine2, prelude::*};
 use kurbo::Affine;
 
+/// The exact 2D part of `transform`: the XY block of its matrix and its XY
+/// translation. Z only affects draw order and is dropped.
+pub fn affine_from_global_transform(transform: &GlobalTransform) -> Affine {
+    let m = transform.compute_matrix();
+    Affine::new([
+        m.x_axis.x as f64,
+        m.x_axis.y as f64,
+        m.y_axis.x as f64,
+        m.y_axis.y as f64,
+        m.w_axis.x as f64,
+        m.w_axis.y as f64,
+    ])
+}
+
+/// The reverse of [`affine_from_global_transform`]: a transform at depth `z`
+/// with the translation, rotation around Z and scale of `affine`. A
+/// `GlobalTransform` can't shear, so any skew of `affine` is dropped, and a
+/// mirrored `affine` comes out with a negative Y scale.
+pub fn global_transform_from_affine(affine: Affine, z: f32) -> GlobalTransform {
+    let [a, b, c, d, e, f] = affine.as_coeffs();
+    let scale_x = a.hypot(b);
+    let scale_y = if scale_x == 0.0 {
+        c.hypot(d)
+    } else {
+        (a * d - b * c) / scale_x
+    };
+    GlobalTransform {
+        translation: Vec3::new(e as f32, f as f32, z),
+        rotation: Quat::from_rotation_z(b.atan2(a) as f32),
+        scale: Vec3::new(scale_x as f32, scale_y as f32, 1.0),
+    }
+}
+
+/// Like [`affine_from_global_transform`], but rotation, scale and skew happen
+/// around `pivot`, given in the local coordinates of the drawn content. The
+/// translation still moves the local origin.
+pub fn affine_from_global_transform_around(
+    transform: &GlobalTransform,
+    pivot: Vec2,
+) -> Affine {
+    let translation = transform.translation;
+    Affine::translate((translation.x as f64, translation.y as f64))
+        * affine_around(
+            Affine::translate((-translation.x as f64, -translation.y as f64))
+                * affine_from_global_transform(transform),
+            pivot,
+        )
+}
+
+pub fn affine_from_affine2(affine: Affine2) -> Affine {
+    let [a, b, c, d, e, f] = affine.to_cols_array();
+    Affine::new([a as f64, b as f64, c as f64, d as f64, e as f64, f as f64])
+}
+
+pub fn affine_to_affine2(affine: Affine) -> Affine2 {
+    let [a, b, c, d, e, f] = affine.as_coeffs();
+    Affine2::from_cols_array(&[
+        a as f32, b as f32, c as f32, d as f32, e as f32, f as f32,
+    ])
+}
+
+/// Convert a 2D homogeneous matrix. The projective bottom row is ignored.
+pub fn affine_from_mat3(mat: Mat3) -> Affine {
+    Affine::new([
+        mat.x_axis.x as f64,
+        mat.x_axis.y as f64,
+        mat.y_axis.x as f64,
+        mat.y_axis.y as f64,
+        mat.z_axis.x as f64,
+        mat.z_axis.y as f64,
+    ])
+}
+
+pub fn affine_to_mat3(affine: Affine) -> Mat3 {
+    let [a, b, c, d, e, f] = affine.as_coeffs();
+    Mat3::from_cols(
+        Vec3::new(a as f32, b as f32, 0.0),
+        Vec3::new(c as f32, d as f32, 0.0),
+        Vec3::new(e as f32, f as f32, 1.0),
+    )
+}
+
+/// Apply `affine` with `pivot` as the origin.
+pub fn affine_around(affine: Affine, pivot: Vec2) -> Affine {
+    let p = kurbo::Vec2::new(pivot.x.into(), pivot.y.into());
+    Affine::translate(p) * affine * Affine::translate(p * -1.0)
+}
+
 pub fn affine_rotate_around(th: f32, p: Vec2) -> Affine {
-    let p = kurbo::Vec2::new(p.x.into(), p.y.into());
-    Affine::translate(p)
-        * Affine::rotate(th.into())
-        * Affine::translate(p * -1.0)
+    affine_around(Affine::rotate(th.into()), p)
 }
 
 pub fn affine_scale_around(s: Vec2, p: Vec2) -> Affine {
-    let p = kurbo::Vec2::new(p.x.into(), p.y.into());
-    Affine::translate(p)
-        * Affine::scale_non_uniform(s.x.into(), s.y.into())
-        * Affine::translate(p * -1.0)
+    affine_around(Affine::scale_non_uniform(s.x.into(), s.y.into()), p)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_3;
+
+    use kurbo::Point;
+
+    use super::*;
+
+    fn assert_affine_eq(a: Affine, b: Affine) {
+        let (a, b) = (a.as_coeffs(), b.as_coeffs());
+        for i in 0..6 {
+            assert!((a[i] - b[i]).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn transform(
+        translation: Vec3,
+        angle: f32,
+        scale: Vec3,
+    ) -> GlobalTransform {
+        GlobalTransform {
+            translation,
+            rotation: Quat::from_rotation_z(angle),
+            scale,
+        }
+    }
+
+    #[test]
+    fn converts_translation_rotation_and_scale() {
+        let t = transform(Vec3::new(10.0, -20.0, 3.0), FRAC_PI_3, Vec3::ONE);
+        let expected =
+            Affine::translate((10.0, -20.0)) * Affine::rotate(FRAC_PI_3 as f64);
+        assert_affine_eq(affine_from_global_transform(&t), expected);
+
+        let t = transform(Vec3::ZERO, 0.0, Vec3::new(2.0, 3.0, 1.0));
+        let p = affine_from_global_transform(&t) * Point::new(1.0, 1.0);
+        assert_eq!(p, Point::new(2.0, 3.0));
+    }
+
+    #[test]
+    fn round_trips_through_global_transform() {
+        let transforms = [
+            transform(Vec3::new(5.0, 7.0, 1.5), 0.0, Vec3::ONE),
+            transform(Vec3::ZERO, FRAC_PI_3, Vec3::ONE),
+            transform(Vec3::ZERO, -2.5, Vec3::new(2.0, 0.5, 1.0)),
+            transform(
+                Vec3::new(-3.0, 4.0, 0.0),
+                1.0,
+                Vec3::new(3.0, -1.0, 1.0),
+            ),
+        ];
+        for t in transforms {
+            let affine = affine_from_global_transform(&t);
+            let back = global_transform_from_affine(affine, t.translation.z);
+            assert_eq!(back.translation, t.translation);
+            assert_affine_eq(affine_from_global_transform(&back), affine);
+        }
+    }
+
+    #[test]
+    fn drops_skew() {
+        let skewed = Affine::new([1.0, 0.0, 1.0, 1.0, 0.0, 0.0]);
+        let t = global_transform_from_affine(skewed, 0.0);
+        assert_eq!(t.rotation, Quat::IDENTITY);
+        assert_eq!(t.scale, Vec3::ONE);
+    }
+
+    #[test]
+    fn round_trips_through_affine2_and_mat3() {
+        let affine = Affine::translate((4.0, -2.0))
+            * Affine::rotate(0.5)
+            * Affine::scale_non_uniform(2.0, 3.0);
+        assert_affine_eq(
+            affine_from_affine2(affine_to_affine2(affine)),
+            affine,
+        );
+        assert_affine_eq(affine_from_mat3(affine_to_mat3(affine)), affine);
+
+        let p = affine_to_mat3(affine).transform_point2(Vec2::new(1.0, 1.0));
+        let q = affine * Point::new(1.0, 1.0);
+        assert!((p.x as f64 - q.x).abs() < 1e-4);
+        assert!((p.y as f64 - q.y).abs() < 1e-4);
+    }
+
+    #[test]
+    fn keeps_the_pivot_in_place() {
+        let pivot = Vec2::new(10.0, 5.0);
+        let rotated = affine_rotate_around(1.0, pivot) * Point::new(10.0, 5.0);
+        assert!((rotated - Point::new(10.0, 5.0)).hypot() < 1e-4);
+        let scaled = affine_scale_around(Vec2::new(2.0, 2.0), pivot)
+            * Point::new(11.0, 5.0);
+        assert_eq!(scaled, Point::new(12.0, 5.0));
+    }
 }
@@ -0,0 +1,226 @@
+use std::{collections::BTreeMap, mem, sync::Arc};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use kurbo::{Affine, BezPath, Circle, Point, Rect, Shape};
+use piet_gpu::Color as PietColor;
+
+use crate::{
+    backend::RenderBackend, custom::CustomDrawable, DrawList,
+    PietCoordinateSystem, PietLayer, PietProducer, RenderCommand, RenderType,
+    RenderWorld, SceneFragment,
+};
+
+/// Accuracy used to turn circles into bezier paths.
+const TOLERANCE: f64 = 0.1;
+
+/// Shapes queued by [`PietPainter`] during the current frame, one fragment per
+/// layer.
+#[derive(Default)]
+pub struct PainterQueue {
+    layers: BTreeMap<PietLayer, SceneFragment>,
+}
+
+/// Per system state of a [`PietPainter`].
+pub struct PainterState {
+    layer: PietLayer,
+    stroke_width: f64,
+}
+
+impl Default for PainterState {
+    fn default() -> Self {
+        Self {
+            layer: PietLayer::FOREGROUND,
+            stroke_width: 1.0,
+        }
+    }
+}
+
+/// Immediate mode drawing from any system, without components or bundles.
+/// Everything drawn is shown for the current frame only, so keep drawing it
+/// every frame. Positions are world coordinates, colors are piet colors so
+/// the painter works without bevy's render crate.
+///
+/// The layer and stroke width are kept per system, and stay set for later
+/// runs of the same system until they are changed again.
+///
+/// ```ignore
+/// fn debug_draw(mut painter: PietPainter, query: Query<&Transform>) {
+///     painter.set_layer(PietLayer(5000));
+///     for transform in query.iter() {
+///         painter.circle(transform.translation.truncate(), 10.0, PietColor::rgb8(0xff, 0, 0));
+///     }
+/// }
+/// ```
+#[derive(SystemParam)]
+pub struct PietPainter<'w, 's> {
+    queue: ResMut<'w, PainterQueue>,
+    coordinate_system: Res<'w, PietCoordinateSystem>,
+    state: Local<'s, PainterState>,
+}
+
+impl<'w, 's> PietPainter<'w, 's> {
+    /// Layer of everything drawn afterwards, [`PietLayer::FOREGROUND`] by
+    /// default.
+    pub fn set_layer(&mut self, layer: PietLayer) -> &mut Self {
+        self.state.layer = layer;
+        self
+    }
+
+    /// Width of the lines drawn afterwards, 1.0 by default.
+    pub fn set_stroke_width(&mut self, width: f32) -> &mut Self {
+        self.state.stroke_width = width as f64;
+        self
+    }
+
+    pub fn line(
+        &mut self,
+        start: Vec2,
+        end: Vec2,
+        color: PietColor,
+    ) -> &mut Self {
+        let mut path = BezPath::new();
+        path.move_to(to_point(start));
+        path.line_to(to_point(end));
+        self.stroke(path, color)
+    }
+
+    /// Outline of the axis aligned rectangle between `min` and `max`.
+    pub fn rect(
+        &mut self,
+        min: Vec2,
+        max: Vec2,
+        color: PietColor,
+    ) -> &mut Self {
+        let rect = Rect::from_points(to_point(min), to_point(max));
+        self.stroke(rect.to_path(TOLERANCE), color)
+    }
+
+    pub fn fill_rect(
+        &mut self,
+        min: Vec2,
+        max: Vec2,
+        color: PietColor,
+    ) -> &mut Self {
+        let rect = Rect::from_points(to_point(min), to_point(max));
+        self.fill(rect.to_path(TOLERANCE), color)
+    }
+
+    pub fn circle(
+        &mut self,
+        center: Vec2,
+        radius: f32,
+        color: PietColor,
+    ) -> &mut Self {
+        let circle = Circle::new(to_point(center), radius as f64);
+        self.stroke(circle.to_path(TOLERANCE), color)
+    }
+
+    pub fn fill_circle(
+        &mut self,
+        center: Vec2,
+        radius: f32,
+        color: PietColor,
+    ) -> &mut Self {
+        let circle = Circle::new(to_point(center), radius as f64);
+        self.fill(circle.to_path(TOLERANCE), color)
+    }
+
+    /// Cubic bezier curve from `p0` to `p3` with control points `p1` and
+    /// `p2`.
+    pub fn cubic_bezier(
+        &mut self,
+        p0: Vec2,
+        p1: Vec2,
+        p2: Vec2,
+        p3: Vec2,
+        color: PietColor,
+    ) -> &mut Self {
+        let mut path = BezPath::new();
+        path.move_to(to_point(p0));
+        path.curve_to(to_point(p1), to_point(p2), to_point(p3));
+        self.stroke(path, color)
+    }
+
+    /// Outline of an arbitrary path.
+    pub fn path(&mut self, path: BezPath, color: PietColor) -> &mut Self {
+        self.stroke(path, color)
+    }
+
+    pub fn fill_path(&mut self, path: BezPath, color: PietColor) -> &mut Self {
+        self.fill(path, color)
+    }
+
+    /// Draw `text` with the top left corner of its layout at `position`,
+    /// upright whatever the coordinate system. Backends draw text in their
+    /// default color.
+    pub fn text(
+        &mut self,
+        position: Vec2,
+        text: &str,
+        font_size: f32,
+    ) -> &mut Self {
+        let affine = Affine::translate(to_point(position).to_vec2())
+            * self.coordinate_system.content_transform();
+        let fragment = self.fragment();
+        fragment.save();
+        fragment.transform(affine);
+        fragment.draw_text(text, font_size as f64, Point::ORIGIN);
+        fragment.restore();
+        self
+    }
+
+    fn stroke(&mut self, path: BezPath, color: PietColor) -> &mut Self {
+        let width = self.state.stroke_width;
+        self.fragment().stroke(&path, &color, width);
+        self
+    }
+
+    fn fill(&mut self, path: BezPath, color: PietColor) -> &mut Self {
+        self.fragment().fill(&path, &color);
+        self
+    }
+
+    fn fragment(&mut self) -> &mut SceneFragment {
+        let layer = self.state.layer;
+        self.queue.layers.entry(layer).or_default()
+    }
+}
+
+fn to_point(v: Vec2) -> Point {
+    Point::new(v.x.into(), v.y.into())
+}
+
+/// Painted fragments are in world coordinates already.
+impl CustomDrawable for SceneFragment {
+    fn draw(&self, rc: &mut dyn RenderBackend, _affine: Affine) {
+        self.replay(rc);
+    }
+}
+
+/// Shapes painted during the last frame, moved over from the app world.
+#[derive(Default)]
+pub(crate) struct ExtractedPainter {
+    layers: BTreeMap<PietLayer, SceneFragment>,
+}
+
+pub(crate) fn extract_painter(
+    mut render_world: ResMut<RenderWorld>,
+    mut queue: ResMut<PainterQueue>,
+) {
+    let layers = mem::take(&mut queue.layers);
+    render_world.insert_resource(ExtractedPainter { layers });
+}
+
+pub(crate) fn prepare_painter(
+    mut extracted: ResMut<ExtractedPainter>,
+    mut draw_list: ResMut<DrawList>,
+) {
+    for (layer, fragment) in mem::take(&mut extracted.layers) {
+        let render_type =
+            RenderType::Custom(Arc::new(fragment), GlobalTransform::identity());
+        draw_list.push(
+            RenderCommand::new(render_type, layer)
+                .producer(PietProducer::of::<PainterQueue>()),
+        );
+    }
+}
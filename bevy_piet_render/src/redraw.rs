@@ -0,0 +1,46 @@
+use bevy::{prelude::*, window::RequestRedraw};
+
+use crate::RenderWorld;
+
+/// Tracks whether the next frame has to be drawn in
+/// [`PietUpdateMode::Reactive`](crate::PietUpdateMode::Reactive).
+pub(crate) struct RedrawState {
+    /// Something outside of the render commands changed, like the camera,
+    /// the viewport or the settings, or a redraw was requested.
+    pub(crate) requested: bool,
+    /// `prepare_frame` drew into the backend and `render_frame` has to
+    /// submit it.
+    pub(crate) drawn: bool,
+    /// Hash of the sort keys of the commands in the last drawn frame.
+    pub(crate) sort_keys: u64,
+}
+
+impl Default for RedrawState {
+    fn default() -> Self {
+        // The first frame is always drawn.
+        Self {
+            requested: true,
+            drawn: false,
+            sort_keys: 0,
+        }
+    }
+}
+
+impl RedrawState {
+    pub(crate) fn request(&mut self) {
+        self.requested = true;
+    }
+}
+
+/// Forward [`RequestRedraw`] events of the app world to the renderer.
+pub(crate) fn extract_redraw_events(
+    mut render_world: ResMut<RenderWorld>,
+    mut events: EventReader<RequestRedraw>,
+) {
+    if events.iter().next().is_some() {
+        render_world
+            .get_resource_mut::<RedrawState>()
+            .unwrap()
+            .request();
+    }
+}
@@ -1,278 +1,849 @@
-use bevy::{math::Vec3Swizzles, prelude::*};
-use kurbo::{Affine, Point};
-use piet_gpu::{
-    PicoSvg, PietGpuRenderContext, RenderContext, Renderer,
-    TextLayoutBuilder, TextAttribute, Text,
+use std::{
+    cmp::Ordering,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
 };
 
-use piet_gpu_hal::{
-    CmdBuf, Error, ImageLayout, Instance, QueryPool, Semaphore, Session,
-    SubmittedCmdBuf, Swapchain,
+use bevy::{
+    ecs::event::Events,
+    prelude::*,
+    window::{WindowResized, WindowScaleFactorChanged},
+};
+use kurbo::{Affine, Point, Rect};
+
+use crate::{
+    backend::{CpuBackend, GpuBackend, PietBackend, RenderBackend},
+    camera::ExtractedView,
+    custom::CustomDrawable,
+    diagnostics::PendingGpuTimings,
+    draw_list::DrawList,
+    error::PendingRenderErrors,
+    math,
+    redraw::RedrawState,
+    target::{ExtractedImageTargets, RenderedImages},
+    PietCoordinateSystem, PietErrorPolicy, PietRenderError, PietRenderSettings,
+    PietUpdateMode, RenderWorld, VectorGraphic,
 };
 
-use crate::math;
+/// Orders the systems of the render app that depend on each other.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+pub(crate) enum RenderSystem {
+    ExtractCamera,
+}
 
-const NUM_FRAMES: usize = 2;
+/// Font size of text labels.
+const TEXT_FONT_SIZE: f64 = 40.0;
 
 pub enum RenderType {
-    Text(String, GlobalTransform),
-    Svg(PicoSvg, GlobalTransform, Vec2),
+    /// Text and the transform of its label. The text is shared, so pushing
+    /// an unchanged label every frame doesn't copy it.
+    Text(Arc<str>, GlobalTransform),
+    /// A graphic shared by all its instances, the transform of the instance
+    /// and the center the graphic rotates and scales around.
+    Svg(Arc<VectorGraphic>, GlobalTransform, Vec2),
+    /// A user-defined drawable and the transform of its entity.
+    Custom(Arc<dyn CustomDrawable>, GlobalTransform),
+}
+
+/// The layer an entity is drawn in. Layers are drawn in ascending order, so
+/// higher layers end up on top. Within a layer, entities are ordered by the
+/// Z translation of their `GlobalTransform`. Any `i32` works as a layer, the
+/// constants only name a few common ones. A user-defined key converts with
+/// `PietLayer(MyLayer::Hud as i32)`.
+#[derive(
+    Debug, Default, Component, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct PietLayer(pub i32);
+
+impl PietLayer {
+    pub const BACKGROUND: PietLayer = PietLayer(-1000);
+    /// Layer of entities without a [`PietLayer`] component, except for text
+    /// labels.
+    pub const MIDDLE: PietLayer = PietLayer(0);
+    /// Layer of text labels without a [`PietLayer`] component.
+    pub const FOREGROUND: PietLayer = PietLayer(1000);
+}
+
+impl From<i32> for PietLayer {
+    fn from(layer: i32) -> Self {
+        PietLayer(layer)
+    }
 }
 
-pub enum RenderLayer {
-    Background,
-    Middle,
-    Foreground,
+/// Identifies what pushed a [`RenderCommand`], so commands with otherwise
+/// equal sort keys are drawn in the same order every frame, whatever order
+/// the systems pushing them ran in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PietProducer(pub u64);
+
+impl PietProducer {
+    /// A producer named after the type `T`, like the component it draws.
+    pub fn of<T: ?Sized>() -> Self {
+        // FNV-1a of the type name, which unlike `TypeId` is the same in
+        // every build.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in std::any::type_name::<T>().bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        PietProducer(hash)
+    }
 }
 
+/// Something to draw in the current frame, pushed into the
+/// [`DrawList`](crate::DrawList). Commands are drawn sorted by layer, then by
+/// the Z translation of their transform, then by the entity of tracked
+/// commands, with commands without an entity first, then by their
+/// [`PietProducer`]. Commands with equal keys keep the order they were pushed
+/// in, which is only deterministic for commands pushed by the same system.
 pub struct RenderCommand {
     render_type: RenderType,
-    render_layer: RenderLayer,
+    layer: PietLayer,
+    z: f32,
+    tracked: Option<Tracked>,
+    producer: PietProducer,
+    /// Position in the draw list, set when pushed.
+    pub(crate) sequence: usize,
+}
+
+/// The entity a command draws and whether it changed since the last frame.
+#[derive(Clone, Copy)]
+struct Tracked {
+    entity: Entity,
+    changed: bool,
 }
 
 impl RenderCommand {
-    pub fn new(render_type: RenderType, render_layer: RenderLayer) -> Self {
+    pub fn new(render_type: RenderType, layer: PietLayer) -> Self {
+        let z = match &render_type {
+            RenderType::Text(_, transform)
+            | RenderType::Svg(_, transform, _)
+            | RenderType::Custom(_, transform) => transform.translation.z,
+        };
         Self {
             render_type,
-            render_layer,
+            layer,
+            z,
+            tracked: None,
+            producer: PietProducer::default(),
+            sequence: 0,
         }
     }
+
+    /// Tell the renderer which `entity` this command draws and whether the
+    /// entity `changed` since the previous frame. In
+    /// [`PietUpdateMode::Reactive`] a frame is only drawn again when a
+    /// tracked command changed, or when any command isn't tracked at all.
+    pub fn tracked(mut self, entity: Entity, changed: bool) -> Self {
+        self.tracked = Some(Tracked { entity, changed });
+        self
+    }
+
+    /// Tell the renderer what pushed this command. Systems of
+    /// [`PietDrawSystem::Queue`](crate::PietDrawSystem::Queue) that may run
+    /// in parallel should each use a producer of their own.
+    pub fn producer(mut self, producer: PietProducer) -> Self {
+        self.producer = producer;
+        self
+    }
+
+    pub(crate) fn cmp_sort_key(&self, other: &Self) -> Ordering {
+        let entity = |command: &Self| command.tracked.map(|t| t.entity);
+        self.layer
+            .cmp(&other.layer)
+            .then_with(|| self.z.total_cmp(&other.z))
+            .then_with(|| entity(self).cmp(&entity(other)))
+            .then_with(|| self.producer.cmp(&other.producer))
+            .then_with(|| self.sequence.cmp(&other.sequence))
+    }
 }
 pub struct RenderFrame {
     pub current_frame: usize,
 }
 
-pub struct RenderResources {
-    present_semaphores: Vec<Semaphore>,
-    query_pools: Vec<QueryPool>,
-    cmd_bufs: [Option<CmdBuf>; NUM_FRAMES],
-    submitted: [Option<piet_gpu_hal::SubmittedCmdBuf>; NUM_FRAMES],
-    session: Session,
-    swapchain: Swapchain,
-    renderer: Renderer,
-}
-
-pub fn setup_piet_renderer(app_world: &World, render_app: &mut App) {
-    let windows = app_world.get_resource::<Windows>().unwrap();
-    let window = windows.get_primary().unwrap();
-
-    let raw_window_handle = unsafe { window.raw_window_handle().get_handle() };
-    let (instance, surface) =
-        Instance::new(Some(&raw_window_handle), Default::default())
-            .expect("Error: failed to creat Piet instance");
-    let device = unsafe {
-        instance
-            .device(surface.as_ref())
-            .expect("Error: Piet device creation failure")
-    };
-    let swapchain = unsafe {
-        instance
-            .swapchain(
-                window.physical_width() as usize / 2,
-                window.physical_height() as usize / 2,
-                &device,
-                surface.as_ref().unwrap(),
+/// Selects where the piet renderer sends its output. Insert it as a resource
+/// before adding [`PietRenderPlugin`](crate::PietRenderPlugin) to change it.
+#[derive(Debug, Clone, Copy)]
+pub enum PietRenderMode {
+    /// Present every frame to the primary window through a swapchain.
+    Windowed,
+    /// Render into an offscreen image of the given physical size without
+    /// creating a surface, and copy the pixels back into [`HeadlessFrame`].
+    Headless { width: u32, height: u32 },
+    /// Render on the CPU with the reference rasterizer instead of piet-gpu,
+    /// writing the pixels into [`HeadlessFrame`]. No GPU device is needed.
+    Cpu { width: u32, height: u32 },
+}
+
+impl Default for PietRenderMode {
+    fn default() -> Self {
+        PietRenderMode::Windowed
+    }
+}
+
+/// RGBA8 pixels read back from the GPU in headless mode, or rendered by the
+/// CPU backend.
+#[derive(Default)]
+pub struct HeadlessImage {
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed RGBA8 rows, top row first.
+    pub data: Vec<u8>,
+    /// The number of the frame these pixels were rendered in.
+    pub frame: usize,
+}
+
+/// Shared access to the latest frame rendered in headless mode. The same
+/// resource lives in both the app world and the render world, so app systems
+/// can read the pixels the renderer wrote. With the GPU backend the image
+/// lags the current frame by the number of frames in flight, or catches up
+/// in the first frame that draws nothing new.
+#[derive(Default, Clone)]
+pub struct HeadlessFrame(Arc<Mutex<HeadlessImage>>);
+
+impl HeadlessFrame {
+    pub fn lock(&self) -> MutexGuard<HeadlessImage> {
+        self.0.lock().unwrap()
+    }
+}
+
+/// Size and scale of the output the renderer draws into.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderViewport {
+    /// Width of the output in physical pixels.
+    pub physical_width: u32,
+    /// Height of the output in physical pixels.
+    pub physical_height: u32,
+    /// Scale factor of the window, 1.0 when rendering headless.
+    pub scale_factor: f64,
+    /// Render scale from [`PietRenderSettings`].
+    pub render_scale: f32,
+}
+
+impl RenderViewport {
+    /// Size of the image the backend renders into, which is the physical size
+    /// multiplied by the render scale.
+    pub fn render_size(&self) -> (usize, usize) {
+        let scale = self.render_scale as f64;
+        (
+            ((self.physical_width as f64 * scale).round() as usize).max(1),
+            ((self.physical_height as f64 * scale).round() as usize).max(1),
+        )
+    }
+
+    /// Size of the output in logical pixels.
+    pub fn logical_size(&self) -> Vec2 {
+        Vec2::new(
+            (self.physical_width as f64 / self.scale_factor) as f32,
+            (self.physical_height as f64 / self.scale_factor) as f32,
+        )
+    }
+
+    /// Transform from logical coordinates to pixels of the rendered image,
+    /// applied at the root of every frame.
+    pub fn root_transform(&self) -> Affine {
+        Affine::scale(self.scale_factor * self.render_scale as f64)
+    }
+}
+
+pub fn setup_piet_renderer(app_world: &mut World, render_app: &mut App) {
+    let mode = app_world
+        .get_resource::<PietRenderMode>()
+        .copied()
+        .unwrap_or_default();
+    let settings = app_world
+        .get_resource::<PietRenderSettings>()
+        .cloned()
+        .unwrap_or_default();
+
+    let (backend, viewport) = match mode {
+        PietRenderMode::Windowed => {
+            let window = app_world
+                .get_resource::<Windows>()
+                .and_then(|windows| windows.get_primary());
+            match window {
+                Some(window) => {
+                    let viewport = RenderViewport {
+                        physical_width: window.physical_width(),
+                        physical_height: window.physical_height(),
+                        scale_factor: window.scale_factor(),
+                        render_scale: settings.render_scale,
+                    };
+                    (
+                        GpuBackend::windowed(window, &viewport, &settings).map(
+                            |backend| {
+                                Box::new(backend) as Box<dyn RenderBackend>
+                            },
+                        ),
+                        viewport,
+                    )
+                }
+                None => (
+                    Err(PietRenderError::InstanceCreation(
+                        "there is no primary window to render into".to_string(),
+                    )),
+                    RenderViewport {
+                        physical_width: 1,
+                        physical_height: 1,
+                        scale_factor: 1.0,
+                        render_scale: settings.render_scale,
+                    },
+                ),
+            }
+        }
+        PietRenderMode::Headless { width, height } => {
+            let viewport = RenderViewport {
+                physical_width: width,
+                physical_height: height,
+                scale_factor: 1.0,
+                render_scale: settings.render_scale,
+            };
+            let frame = insert_headless_frame(app_world, render_app, &viewport);
+            (
+                GpuBackend::headless(&viewport, &settings, frame)
+                    .map(|backend| Box::new(backend) as Box<dyn RenderBackend>),
+                viewport,
             )
-            .unwrap()
+        }
+        PietRenderMode::Cpu { width, height } => {
+            let viewport = RenderViewport {
+                physical_width: width,
+                physical_height: height,
+                scale_factor: 1.0,
+                render_scale: settings.render_scale,
+            };
+            let frame = insert_headless_frame(app_world, render_app, &viewport);
+            (
+                CpuBackend::new(&viewport, &settings, frame)
+                    .map(|backend| Box::new(backend) as Box<dyn RenderBackend>),
+                viewport,
+            )
+        }
     };
-    let session = Session::new(device);
-
-    let query_pools = (0..NUM_FRAMES)
-        .map(|_| session.create_query_pool(8))
-        .collect::<Result<Vec<_>, Error>>()
-        .unwrap();
-    let cmd_bufs: [Option<CmdBuf>; NUM_FRAMES] = Default::default();
-    let submitted: [Option<SubmittedCmdBuf>; NUM_FRAMES] = Default::default();
-
-    unsafe {
-        let present_semaphores = (0..NUM_FRAMES)
-            .map(|_| session.create_semaphore())
-            .collect::<Result<Vec<_>, Error>>()
-            .unwrap();
-
-        let renderer = Renderer::new(
-            &session,
-            window.physical_width() as usize,
-            window.physical_height() as usize,
-            NUM_FRAMES,
-        )
-        .expect("Error: Piet renderer creation failure");
 
-        render_app.insert_resource(RenderFrame { current_frame: 0 });
+    render_app.insert_resource(RenderFrame { current_frame: 0 });
+    render_app.insert_resource(viewport);
+    render_app.insert_resource(settings.clone());
+    render_app.init_resource::<PendingRenderErrors>();
+
+    match backend {
+        Ok(backend) => {
+            render_app.insert_non_send_resource(PietBackend(backend));
+        }
+        Err(error) => {
+            // Without a backend the render app keeps running but draws
+            // nothing.
+            if let PietErrorPolicy::Panic = settings.error_policy {
+                panic!("Error: {}", error);
+            }
+            error!("{}", error);
+            app_world
+                .get_resource_mut::<Events<PietRenderError>>()
+                .unwrap()
+                .send(error);
+        }
+    }
+}
+
+/// Share a new [`HeadlessFrame`] of the render size between the app world and
+/// the render world.
+fn insert_headless_frame(
+    app_world: &mut World,
+    render_app: &mut App,
+    viewport: &RenderViewport,
+) -> HeadlessFrame {
+    let frame = HeadlessFrame::default();
+    {
+        let (width, height) = viewport.render_size();
+        let mut image = frame.lock();
+        image.width = width as u32;
+        image.height = height as u32;
+    }
+    app_world.insert_resource(frame.clone());
+    render_app.insert_resource(frame.clone());
+    frame
+}
+
+/// Watch the primary window for resizes and scale factor changes and update
+/// the [`RenderViewport`] of the render world.
+pub fn extract_window_viewport(
+    mut render_world: ResMut<RenderWorld>,
+    mut resized_events: EventReader<WindowResized>,
+    mut scale_factor_events: EventReader<WindowScaleFactorChanged>,
+    windows: Res<Windows>,
+) {
+    let resized = resized_events.iter().any(|event| event.id.is_primary());
+    let rescaled = scale_factor_events
+        .iter()
+        .any(|event| event.id.is_primary());
+    if !resized && !rescaled {
+        return;
+    }
+
+    if let Some(window) = windows.get_primary() {
+        let mut viewport =
+            render_world.get_resource_mut::<RenderViewport>().unwrap();
+        viewport.physical_width = window.physical_width();
+        viewport.physical_height = window.physical_height();
+        viewport.scale_factor = window.scale_factor();
+    }
+}
+
+/// Apply changed [`PietRenderSettings`] to the viewport and the backend. Runs
+/// in the [`Setup`](crate::PietRenderStage::Setup) stage, so settings
+/// extracted during a frame are applied at the start of the next one, before
+/// anything of it is extracted.
+pub fn apply_render_settings(
+    settings: Res<PietRenderSettings>,
+    mut viewport: ResMut<RenderViewport>,
+    backend: Option<NonSendMut<PietBackend>>,
+    mut errors: ResMut<PendingRenderErrors>,
+    mut redraw: ResMut<RedrawState>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    redraw.request();
 
-        render_app.insert_resource(PietGpuRenderContext::new());
+    // Picked up by `resize_renderer`.
+    if viewport.render_scale != settings.render_scale {
+        viewport.render_scale = settings.render_scale;
+    }
 
-        render_app.insert_non_send_resource(Some(RenderResources {
-            present_semaphores,
-            query_pools,
-            cmd_bufs,
-            submitted,
-            session,
-            swapchain,
-            renderer,
-        }));
+    if let Some(mut backend) = backend {
+        if let Err(error) = backend.configure(&settings) {
+            errors.report(error, settings.error_policy);
+        }
+    }
+}
 
-        // Keep instance from being dropped
-        render_app.insert_non_send_resource(instance);
+/// Sleep at the end of a frame to stay below
+/// [`PietRenderSettings::frame_rate_limit`].
+pub fn limit_frame_rate(
+    settings: Res<PietRenderSettings>,
+    mut last_frame: Local<Option<Instant>>,
+) {
+    if let (Some(limit), Some(last_frame)) =
+        (settings.frame_rate_limit, *last_frame)
+    {
+        if limit > 0.0 {
+            let frame_time = Duration::from_secs_f64(1.0 / limit);
+            let elapsed = last_frame.elapsed();
+            if elapsed < frame_time {
+                thread::sleep(frame_time - elapsed);
+            }
+        }
+    }
+    *last_frame = Some(Instant::now());
+}
+
+/// Recreate the size dependent resources of the backend, like the swapchain,
+/// the renderer buffers and the query pools of piet-gpu, when the size of the
+/// [`RenderViewport`] changes.
+pub fn resize_renderer(
+    viewport: Res<RenderViewport>,
+    backend: Option<NonSendMut<PietBackend>>,
+    settings: Res<PietRenderSettings>,
+    mut errors: ResMut<PendingRenderErrors>,
+    mut redraw: ResMut<RedrawState>,
+) {
+    let mut backend = match backend {
+        Some(backend) => backend,
+        None => return,
     };
+
+    // A minimized window reports a size of zero, keep the old resources
+    // until it is restored.
+    if viewport.physical_width == 0 || viewport.physical_height == 0 {
+        return;
+    }
+
+    if viewport.is_changed() {
+        if let Err(error) = backend.resize(&viewport) {
+            errors.report(error, settings.error_policy);
+        }
+        redraw.request();
+    }
 }
 
-/// Prepare the render context by drawing elements to it in the order of their
-/// respective render layers
+/// Prepare the render context by drawing the [`DrawList`] into it in the
+/// order of the sort keys, see [`RenderCommand`]. Elements whose bounds lie
+/// completely outside of the viewport are skipped before they are drawn.
+/// The commands are then drawn again into every image target, culled
+/// against the view of its camera.
+///
+/// In [`PietUpdateMode::Reactive`] nothing is drawn unless a redraw was
+/// requested or the commands differ from the previous frame.
 pub fn prepare_frame(
-    mut ctx: ResMut<PietGpuRenderContext>,
-    mut events: EventReader<RenderCommand>,
+    backend: Option<NonSendMut<PietBackend>>,
+    mut draw_list: ResMut<DrawList>,
+    viewport: Res<RenderViewport>,
+    view: Res<ExtractedView>,
+    coordinate_system: Res<PietCoordinateSystem>,
+    settings: Res<PietRenderSettings>,
+    mut redraw: ResMut<RedrawState>,
+    image_targets: Res<ExtractedImageTargets>,
+    mut errors: ResMut<PendingRenderErrors>,
 ) {
-    let events: Vec<&RenderCommand> = events.iter().collect();
-    for &command in events
-        .iter()
-        .filter(|c| matches!(c.render_layer, RenderLayer::Background { .. }))
+    let mut backend = match backend {
+        Some(backend) => backend,
+        None => return,
+    };
+
+    draw_list.sort();
+    let sort_keys = hash_sort_keys(draw_list.commands());
+    if settings.update_mode == PietUpdateMode::Reactive
+        && !redraw.requested
+        && sort_keys == redraw.sort_keys
+        && !commands_changed(draw_list.commands())
     {
-        execute_render_command(&mut ctx, command);
+        return;
     }
-    for &command in events
-        .iter()
-        .filter(|c| matches!(c.render_layer, RenderLayer::Middle { .. }))
-    {
-        execute_render_command(&mut ctx, command);
+    redraw.requested = false;
+    redraw.drawn = true;
+    redraw.sort_keys = sort_keys;
+
+    let backend = &mut **backend;
+    backend.begin_frame();
+    backend.transform(viewport.root_transform() * view.0);
+
+    let logical_size = viewport.logical_size();
+    let viewport_rect =
+        Rect::new(0.0, 0.0, logical_size.x.into(), logical_size.y.into());
+
+    let commands = draw_list.commands();
+    draw_commands(backend, commands, *coordinate_system, view.0, viewport_rect);
+
+    for target in &image_targets.targets {
+        if let Err(error) = backend.begin_image(target.width, target.height) {
+            errors.report(error, settings.error_policy);
+            continue;
+        }
+        backend.transform(target.view);
+        let image_rect =
+            Rect::new(0.0, 0.0, target.width.into(), target.height.into());
+        draw_commands(
+            backend,
+            commands,
+            *coordinate_system,
+            target.view,
+            image_rect,
+        );
+        backend.end_image(target.id);
     }
-    for &command in events
-        .iter()
-        .filter(|c| matches!(c.render_layer, RenderLayer::Foreground { .. }))
-    {
-        execute_render_command(&mut ctx, command);
+}
+
+/// Draw `commands` in order, skipping those whose bounds lie completely
+/// outside of `viewport_rect` once transformed by `view`.
+fn draw_commands(
+    backend: &mut dyn RenderBackend,
+    commands: &[RenderCommand],
+    coordinate_system: PietCoordinateSystem,
+    view: Affine,
+    viewport_rect: Rect,
+) {
+    for command in commands {
+        let affine = command_affine(command, coordinate_system);
+        if let Some(bounds) = command_bounds(command) {
+            let bounds = (view * affine).transform_rect_bbox(bounds);
+            if !overlaps(bounds, viewport_rect) {
+                continue;
+            }
+        }
+        execute_render_command(backend, command, affine);
+    }
+}
+
+/// Whether some of `commands` are not tracked or changed since the previous
+/// frame.
+fn commands_changed(commands: &[RenderCommand]) -> bool {
+    commands.iter().any(|command| match command.tracked {
+        Some(tracked) => tracked.changed,
+        None => true,
+    })
+}
+
+/// Hash of the sort keys of the sorted `commands`, which tells whether
+/// commands were added, removed or moved to another place in the frame.
+fn hash_sort_keys(commands: &[RenderCommand]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for command in commands {
+        command.layer.hash(&mut hasher);
+        command.z.to_bits().hash(&mut hasher);
+        command
+            .tracked
+            .map(|tracked| tracked.entity)
+            .hash(&mut hasher);
+        command.producer.hash(&mut hasher);
     }
+    hasher.finish()
+}
+
+/// Transform from the local coordinates of the drawn content to the world.
+fn command_affine(
+    command: &RenderCommand,
+    coordinate_system: PietCoordinateSystem,
+) -> Affine {
+    match &command.render_type {
+        RenderType::Text(_, trans) => text_affine(*trans, coordinate_system),
+        RenderType::Svg(_, trans, center) => {
+            svg_affine(*trans, *center, coordinate_system)
+        }
+        // Local coordinates point Y down like those of SVGs and text.
+        RenderType::Custom(_, trans) => {
+            math::affine_from_global_transform(trans)
+                * coordinate_system.content_transform()
+        }
+    }
+}
+
+/// Bounds of the drawn content in its local coordinates, `None` if unknown.
+fn command_bounds(command: &RenderCommand) -> Option<Rect> {
+    match &command.render_type {
+        RenderType::Text(text, _) => {
+            Some(estimate_text_bounds(text, TEXT_FONT_SIZE))
+        }
+        RenderType::Svg(svg, _, _) => svg.bounds(),
+        RenderType::Custom(drawable, _) => drawable.bounds(),
+    }
+}
+
+/// Generous bounds of `text` drawn at `font_size`, without laying it out. No
+/// glyph is expected to advance by more than the font size or to reach
+/// further than half of it past its line, and lines are at most twice the
+/// font size apart.
+pub(crate) fn estimate_text_bounds(text: &str, font_size: f64) -> Rect {
+    let lines = text.lines().count().max(1);
+    let columns = text
+        .lines()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    Rect::new(
+        0.0,
+        0.0,
+        columns as f64 * font_size,
+        lines as f64 * font_size * 2.0,
+    )
+    .inflate(font_size / 2.0, font_size / 2.0)
+}
+
+/// Like `Rect::intersect` followed by an emptiness check, but keeps
+/// zero-width bounds of straight lines.
+fn overlaps(a: Rect, b: Rect) -> bool {
+    a.x0 <= b.x1 && b.x0 <= a.x1 && a.y0 <= b.y1 && b.y0 <= a.y1
 }
 
 /// Draw an element to the render context according to the render command
 fn execute_render_command(
-    rc: &mut PietGpuRenderContext,
+    rc: &mut dyn RenderBackend,
     command: &RenderCommand,
+    affine: Affine,
 ) {
+    rc.save();
     match &command.render_type {
-        RenderType::Text(text, trans) => render_text(rc, text, *trans),
-        RenderType::Svg(svg, trans, center) => {
-            render_svg(svg, rc, *trans, *center)
+        RenderType::Text(text, _) => {
+            rc.transform(affine);
+            rc.draw_text(text, TEXT_FONT_SIZE, Point::ORIGIN)
         }
+        RenderType::Svg(svg, _, _) => {
+            rc.transform(affine);
+            svg.render(rc)
+        }
+        RenderType::Custom(drawable, _) => drawable.draw(rc, affine),
     }
+    rc.restore();
 }
 
 pub fn render_frame(
-    mut renderer_res: NonSendMut<Option<RenderResources>>,
+    backend: Option<NonSendMut<PietBackend>>,
     mut frame: ResMut<RenderFrame>,
-    mut ctx: ResMut<PietGpuRenderContext>,
+    settings: Res<PietRenderSettings>,
+    mut errors: ResMut<PendingRenderErrors>,
+    gpu_timings: Option<ResMut<PendingGpuTimings>>,
+    mut redraw: ResMut<RedrawState>,
+    image_targets: Res<ExtractedImageTargets>,
+    mut rendered_images: ResMut<RenderedImages>,
 ) {
-    unsafe {
-        let RenderResources {
-            present_semaphores,
-            query_pools,
-            mut cmd_bufs,
-            mut submitted,
-            session,
-            mut swapchain,
-            mut renderer,
-        } = renderer_res.take().unwrap();
-
-        let frame_idx = frame.current_frame % NUM_FRAMES;
-
-        if let Some(submitted) = submitted[frame_idx].take() {
-            cmd_bufs[frame_idx] = submitted.wait().unwrap();
-            let _ts = session.fetch_query_pool(&query_pools[frame_idx]).unwrap();
+    let mut backend = match backend {
+        Some(backend) => backend,
+        None => return,
+    };
+    if !redraw.drawn {
+        // Nothing new to draw, but earlier frames may still hold
+        // screenshots, images or the headless frame.
+        if let Err(error) = backend.flush() {
+            errors.report(error, settings.error_policy);
         }
+    } else {
+        redraw.drawn = false;
+        submit_frame(&mut **backend, &mut frame, &settings, &mut errors);
+    }
 
-        if let Err(e) = renderer.upload_render_ctx(&mut ctx, frame_idx) {
-            println!("error in uploading: {}", e);
+    // Images of cameras that stopped rendering into them are dropped.
+    for (id, image) in backend.take_rendered_images() {
+        if let Some(target) =
+            image_targets.targets.iter().find(|target| target.id == id)
+        {
+            rendered_images.0.push((target.handle, image.data));
         }
-        *ctx = PietGpuRenderContext::new();
-
-        let (image_idx, acquisition_semaphore) = swapchain.next().unwrap();
-        let swap_image = swapchain.image(image_idx);
-        let mut cmd_buf = cmd_bufs[frame_idx]
-            .take()
-            .unwrap_or_else(|| session.cmd_buf().unwrap());
-        cmd_buf.begin();
-        renderer.record(&mut cmd_buf, &query_pools[frame_idx], frame_idx);
-
-        // Image -> Swapchain
-        cmd_buf.image_barrier(
-            &swap_image,
-            ImageLayout::Undefined,
-            ImageLayout::BlitDst,
-        );
-        cmd_buf.blit_image(&renderer.image_dev, &swap_image);
-        cmd_buf.image_barrier(
-            &swap_image,
-            ImageLayout::BlitDst,
-            ImageLayout::Present,
-        );
-        cmd_buf.finish();
-
-        submitted[frame_idx] = Some(
-            session
-                .run_cmd_buf(
-                    cmd_buf,
-                    &[&acquisition_semaphore],
-                    &[&present_semaphores[frame_idx]],
-                )
-                .unwrap(),
-        );
-
-        swapchain
-            .present(image_idx, &[&present_semaphores[frame_idx]])
-            .unwrap();
+    }
 
-        frame.current_frame += 1;
+    if let Some(mut gpu_timings) = gpu_timings {
+        if let Some(timings) = backend.take_gpu_timings() {
+            gpu_timings.0 = Some(timings);
+        }
+    }
+}
 
-        *renderer_res = Some(RenderResources {
-            present_semaphores,
-            query_pools,
-            cmd_bufs,
-            submitted,
-            session,
-            swapchain,
-            renderer,
-        });
+/// End the drawn frame, recovering from errors and retrying as the
+/// [`PietErrorPolicy`] says.
+fn submit_frame(
+    backend: &mut dyn RenderBackend,
+    frame: &mut RenderFrame,
+    settings: &PietRenderSettings,
+    errors: &mut PendingRenderErrors,
+) {
+    let mut attempts = 0;
+    while let Err(error) = backend.end_frame(frame.current_frame) {
+        // Recover whatever the policy, so a skipped frame doesn't leave an
+        // out of date swapchain behind for the next one.
+        let recoverable = error.is_recoverable();
+        errors.report(error.clone(), settings.error_policy);
+        let recovered = recoverable
+            && match backend.recover(&error) {
+                Ok(()) => true,
+                Err(error) => {
+                    errors.report(error, settings.error_policy);
+                    false
+                }
+            };
+        let retry = match settings.error_policy {
+            PietErrorPolicy::Retry { max_attempts } => attempts < max_attempts,
+            _ => false,
+        };
+        if !recovered || !retry {
+            // Skip the frame
+            break;
+        }
+        attempts += 1;
     }
+    frame.current_frame += 1;
 }
 
-pub fn render_svg(
-    svg: &PicoSvg,
-    rc: &mut PietGpuRenderContext,
+fn svg_affine(
     transform: GlobalTransform,
     center: Vec2,
-) {
-    let trans = kurbo::Vec2::new(
-        transform.translation.x as f64,
-        transform.translation.y as f64,
-    );
-    let rotation_z = transform.rotation.to_euler(EulerRot::XYZ).2;
-
-    rc.save().unwrap();
-    rc.transform(
-        Affine::translate(trans)
-            * math::affine_scale_around(transform.scale.xy(), center)
-            * math::affine_rotate_around(rotation_z, center),
-    );
-    svg.render(rc);
-    rc.restore().unwrap();
-}
-
-pub fn render_text(
-    rc: &mut PietGpuRenderContext,
-    text: &str,
+    coordinate_system: PietCoordinateSystem,
+) -> Affine {
+    // The SVG is authored Y-down, flip it upright first and pivot around
+    // where its center ends up.
+    let content = coordinate_system.content_transform();
+    let pivot = content * Point::new(center.x.into(), center.y.into());
+    math::affine_from_global_transform_around(
+        &transform,
+        Vec2::new(pivot.x as f32, pivot.y as f32),
+    ) * content
+}
+
+fn text_affine(
     transform: GlobalTransform,
-) {
-    let layout = rc
-        .text()
-        .new_text_layout(text.to_string())
-        .default_attribute(TextAttribute::FontSize(40.0))
-        .build()
-        .unwrap();
-    rc.draw_text(
-        &layout,
-        Point::new(
-            transform.translation.x.into(),
-            transform.translation.y.into(),
-        ),
-    );
+    coordinate_system: PietCoordinateSystem,
+) -> Affine {
+    // Keep the glyphs upright, the layout hangs below the origin.
+    math::affine_from_global_transform(&transform)
+        * coordinate_system.content_transform()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(layer: i32, z: f32, entity: Option<u32>) -> RenderCommand {
+        let graphic = Arc::new(VectorGraphic::default());
+        let transform = GlobalTransform::from_xyz(0.0, 0.0, z);
+        let command = RenderCommand::new(
+            RenderType::Svg(graphic, transform, Vec2::ZERO),
+            PietLayer(layer),
+        );
+        match entity {
+            Some(id) => command.tracked(Entity::from_raw(id), false),
+            None => command,
+        }
+    }
+
+    fn sorted(mut commands: Vec<RenderCommand>) -> Vec<(i32, f32, usize)> {
+        for (sequence, command) in commands.iter_mut().enumerate() {
+            command.sequence = sequence;
+        }
+        commands.sort_by(RenderCommand::cmp_sort_key);
+        commands
+            .iter()
+            .map(|c| (c.layer.0, c.z, c.sequence))
+            .collect()
+    }
+
+    #[test]
+    fn sorts_by_layer_then_z() {
+        let order = sorted(vec![
+            command(1, 0.0, None),
+            command(0, 2.0, None),
+            command(0, -1.0, None),
+            command(-5, 9.0, None),
+        ]);
+        assert_eq!(
+            order,
+            vec![(-5, 9.0, 3), (0, -1.0, 2), (0, 2.0, 1), (1, 0.0, 0)]
+        );
+    }
+
+    #[test]
+    fn breaks_ties_by_entity_then_push_order() {
+        let order = sorted(vec![
+            command(0, 0.0, Some(7)),
+            command(0, 0.0, None),
+            command(0, 0.0, Some(2)),
+            command(0, 0.0, None),
+        ]);
+        let sequences: Vec<_> = order.iter().map(|o| o.2).collect();
+        assert_eq!(sequences, vec![1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn breaks_ties_by_producer_whatever_the_push_order() {
+        let order = |producers: [u64; 3]| {
+            let commands = producers
+                .iter()
+                .map(|&p| command(0, 0.0, None).producer(PietProducer(p)))
+                .collect();
+            let order = sorted(commands);
+            order.iter().map(|o| producers[o.2]).collect::<Vec<_>>()
+        };
+        assert_eq!(order([2, 1, 3]), vec![1, 2, 3]);
+        assert_eq!(order([3, 2, 1]), vec![1, 2, 3]);
+        assert_ne!(PietProducer::of::<u8>(), PietProducer::of::<u16>());
+    }
+
+    #[test]
+    fn sort_keys_change_with_the_commands() {
+        let frame = || {
+            vec![
+                command(0, 0.0, None),
+                command(0, 1.0, Some(3)),
+                command(1, 0.0, Some(4)),
+            ]
+        };
+        let previous = hash_sort_keys(&frame());
+        assert_eq!(hash_sort_keys(&frame()), previous);
+
+        // An untracked command went away.
+        assert_ne!(hash_sort_keys(&frame()[1..]), previous);
+        // A tracked command moved to another layer.
+        let mut moved = frame();
+        moved[2] = command(2, 0.0, Some(4));
+        assert_ne!(hash_sort_keys(&moved), previous);
+    }
 }
@@ -0,0 +1,218 @@
+use std::{
+    fs, mem,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool, window::RequestRedraw};
+
+use crate::{
+    redraw::RedrawState, render::RenderFrame, HeadlessImage, PietBackend,
+    PietRenderError, RenderWorld,
+};
+
+/// Send this event to capture the next frame the renderer finishes, at the
+/// render size. The frame is encoded as PNG in a background task, and a
+/// [`PietScreenshotTaken`] event reports when that is done.
+#[derive(Clone)]
+pub enum TakePietScreenshot {
+    /// Save the PNG file at this path.
+    Path(PathBuf),
+    /// Hand the PNG encoded bytes to the callback, on a task pool thread.
+    Callback(Arc<dyn Fn(&[u8]) + Send + Sync>),
+}
+
+impl TakePietScreenshot {
+    pub fn path(path: impl Into<PathBuf>) -> Self {
+        TakePietScreenshot::Path(path.into())
+    }
+
+    pub fn callback(callback: impl Fn(&[u8]) + Send + Sync + 'static) -> Self {
+        TakePietScreenshot::Callback(Arc::new(callback))
+    }
+
+    fn saved_path(&self) -> Option<PathBuf> {
+        match self {
+            TakePietScreenshot::Path(path) => Some(path.clone()),
+            TakePietScreenshot::Callback(_) => None,
+        }
+    }
+}
+
+/// Sent once a [`TakePietScreenshot`] request is done.
+#[derive(Debug, Clone)]
+pub struct PietScreenshotTaken {
+    /// Where the screenshot was saved, `None` for callbacks.
+    pub path: Option<PathBuf>,
+    /// The number of the captured frame, or why there is no screenshot.
+    pub result: Result<usize, PietRenderError>,
+}
+
+/// Screenshots finished by background tasks, waiting to be sent as events.
+#[derive(Default)]
+pub(crate) struct FinishedScreenshots(Arc<Mutex<Vec<PietScreenshotTaken>>>);
+
+/// The frame captured for a screenshot, or why it could not be captured.
+type CapturedFrame = Result<Arc<HeadlessImage>, PietRenderError>;
+
+/// Screenshot requests in the render world.
+#[derive(Default)]
+pub(crate) struct PendingScreenshots {
+    /// Not handed to the backend yet.
+    requested: Vec<TakePietScreenshot>,
+    /// Waiting for the backend to read back the frame with the given number
+    /// or a later one.
+    capturing: Vec<(TakePietScreenshot, usize)>,
+    /// Captured or failed, waiting to be encoded in the app world.
+    done: Vec<(TakePietScreenshot, CapturedFrame)>,
+}
+
+/// Move new requests into the render world, and start encoding the frames
+/// captured for earlier ones. Requests a redraw for as long as screenshots
+/// are outstanding, so apps that only update on demand keep updating until
+/// every [`PietScreenshotTaken`] was sent.
+pub(crate) fn extract_screenshots(
+    mut render_world: ResMut<RenderWorld>,
+    mut requests: EventReader<TakePietScreenshot>,
+    mut taken: EventWriter<PietScreenshotTaken>,
+    mut redraws: EventWriter<RequestRedraw>,
+    finished: Res<FinishedScreenshots>,
+    task_pool: Res<AsyncComputeTaskPool>,
+) {
+    for event in finished.0.lock().unwrap().drain(..) {
+        taken.send(event);
+    }
+
+    let requested: Vec<_> = requests.iter().cloned().collect();
+    if !requested.is_empty() {
+        // Reactive mode may not draw another frame otherwise.
+        render_world
+            .get_resource_mut::<RedrawState>()
+            .unwrap()
+            .request();
+    }
+
+    let mut pending = render_world
+        .get_resource_mut::<PendingScreenshots>()
+        .unwrap();
+    pending.requested.extend(requested);
+    for (request, image) in pending.done.drain(..) {
+        let image = match image {
+            Ok(image) => image,
+            Err(error) => {
+                taken.send(PietScreenshotTaken {
+                    path: request.saved_path(),
+                    result: Err(error),
+                });
+                continue;
+            }
+        };
+        let results = finished.0.clone();
+        task_pool
+            .spawn(async move {
+                let result = save_screenshot(&request, &image)
+                    .map(|_| image.frame)
+                    .map_err(|error| {
+                        error!("{}", error);
+                        error
+                    });
+                results.lock().unwrap().push(PietScreenshotTaken {
+                    path: request.saved_path(),
+                    result,
+                });
+            })
+            .detach();
+    }
+
+    // Every encoding task holds a clone of the results until it finished.
+    if !pending.capturing.is_empty()
+        || Arc::strong_count(&finished.0) > 1
+        || !finished.0.lock().unwrap().is_empty()
+    {
+        redraws.send(RequestRedraw);
+    }
+}
+
+/// Ask the backend to capture the frame about to be rendered.
+pub(crate) fn request_screenshots(
+    backend: Option<NonSendMut<PietBackend>>,
+    mut pending: ResMut<PendingScreenshots>,
+    frame: Res<RenderFrame>,
+) {
+    let mut backend = match backend {
+        Some(backend) => backend,
+        None => return,
+    };
+    if pending.requested.is_empty() {
+        return;
+    }
+
+    let requested = mem::take(&mut pending.requested);
+    match backend.capture_next_frame() {
+        Ok(()) => pending.capturing.extend(
+            requested
+                .into_iter()
+                .map(|request| (request, frame.current_frame)),
+        ),
+        Err(error) => pending.done.extend(
+            requested
+                .into_iter()
+                .map(|request| (request, Err(error.clone()))),
+        ),
+    }
+}
+
+/// Hand the frames the backend read back to the requests waiting for them.
+pub(crate) fn collect_screenshots(
+    backend: Option<NonSendMut<PietBackend>>,
+    mut pending: ResMut<PendingScreenshots>,
+) {
+    let mut backend = match backend {
+        Some(backend) => backend,
+        None => return,
+    };
+
+    for image in backend.take_captured_frames() {
+        let image = Arc::new(image);
+        let (ready, waiting): (Vec<_>, Vec<_>) =
+            mem::take(&mut pending.capturing)
+                .into_iter()
+                .partition(|(_, frame)| *frame <= image.frame);
+        pending.capturing = waiting;
+        pending.done.extend(
+            ready
+                .into_iter()
+                .map(|(request, _)| (request, Ok(image.clone()))),
+        );
+    }
+}
+
+fn save_screenshot(
+    request: &TakePietScreenshot,
+    image: &HeadlessImage,
+) -> Result<(), PietRenderError> {
+    let png = encode_png(image)
+        .map_err(|e| PietRenderError::Screenshot(e.to_string()))?;
+    match request {
+        TakePietScreenshot::Path(path) => fs::write(path, png).map_err(|e| {
+            PietRenderError::Screenshot(format!("{}: {}", path.display(), e))
+        }),
+        TakePietScreenshot::Callback(callback) => {
+            callback(&png);
+            Ok(())
+        }
+    }
+}
+
+fn encode_png(image: &HeadlessImage) -> Result<Vec<u8>, png::EncodingError> {
+    let mut png = Vec::new();
+    {
+        let mut encoder =
+            png::Encoder::new(&mut png, image.width, image.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&image.data)?;
+    }
+    Ok(png)
+}
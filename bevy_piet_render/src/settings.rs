@@ -0,0 +1,73 @@
+use piet_gpu::Color;
+
+use crate::{ExtractResource, PietErrorPolicy};
+
+/// When the render sub-app draws a new frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PietUpdateMode {
+    /// Draw and present a frame on every update.
+    Continuous,
+    /// Only draw when something extracted changed, the camera moved, the
+    /// viewport or settings changed, or a `RequestRedraw` event was sent.
+    /// Otherwise the previous frame stays on screen and nothing is uploaded
+    /// or submitted to the GPU.
+    Reactive,
+}
+
+impl Default for PietUpdateMode {
+    fn default() -> Self {
+        PietUpdateMode::Continuous
+    }
+}
+
+/// Settings of the piet renderer. Insert it as a resource before adding
+/// [`PietRenderPlugin`](crate::PietRenderPlugin) to change the initial
+/// settings. Changes made to the resource later on are extracted, and applied
+/// by the [`Setup`](crate::PietRenderStage::Setup) stage of the next frame.
+///
+/// The present mode is not a setting: the pinned piet-gpu-hal picks it when
+/// creating the swapchain, mailbox where the driver supports it and FIFO
+/// otherwise.
+#[derive(Debug, Clone)]
+pub struct PietRenderSettings {
+    /// Resolution of the rendered image relative to the physical size of the
+    /// output. Values above 1.0 supersample, values below 1.0 render at a
+    /// lower resolution and get scaled up when presented.
+    pub render_scale: f32,
+    /// What to do when rendering a frame fails.
+    pub error_policy: PietErrorPolicy,
+    /// Color every frame starts from. Defaults to transparent black, which
+    /// only shows through on a window created with
+    /// `WindowDescriptor::transparent` on a compositor that supports it.
+    pub clear_color: Color,
+    /// Number of frames the CPU may record ahead of the GPU. More frames
+    /// smooth out stalls at the cost of latency, and in headless mode the
+    /// [`HeadlessFrame`](crate::HeadlessFrame) lags by this many frames.
+    pub frames_in_flight: usize,
+    /// Upper bound on the number of frames rendered per second, unlimited
+    /// when `None`.
+    pub frame_rate_limit: Option<f64>,
+    /// Whether to draw every frame or only when something changed.
+    pub update_mode: PietUpdateMode,
+}
+
+impl Default for PietRenderSettings {
+    fn default() -> Self {
+        Self {
+            render_scale: 1.0,
+            error_policy: PietErrorPolicy::default(),
+            clear_color: Color::rgba8(0, 0, 0, 0),
+            frames_in_flight: 2,
+            frame_rate_limit: None,
+            update_mode: PietUpdateMode::default(),
+        }
+    }
+}
+
+impl ExtractResource for PietRenderSettings {
+    type Source = Self;
+
+    fn extract_resource(source: &Self) -> Self {
+        source.clone()
+    }
+}
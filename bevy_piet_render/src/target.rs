@@ -0,0 +1,221 @@
+#[cfg(feature = "bevy_render")]
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use bevy::{asset::HandleId, prelude::*};
+use kurbo::Affine;
+
+#[cfg(feature = "bevy_render")]
+use crate::{
+    redraw::RedrawState, PietCamera2d, PietCoordinateSystem, RenderWorld,
+};
+
+/// Where a [`PietCamera2d`](crate::PietCamera2d) sends what it sees. Cameras without this
+/// component render into the window, or into the
+/// [`HeadlessFrame`](crate::HeadlessFrame) when rendering headless.
+///
+/// A camera rendering into an image draws the whole scene again into an
+/// image of the size of the `Image` asset, with one logical pixel per image
+/// pixel. The image is rendered together with the frame, and its pixels are
+/// written into the asset data once the GPU finished that frame, so up to
+/// `frames_in_flight` frames later. The image should use an RGBA8 format like
+/// `Rgba8UnormSrgb`. This works for minimaps, thumbnails or icons, but every
+/// image costs an extra render pass.
+///
+/// `Image` assets come with bevy's render crate, so the `Image` variant only
+/// exists with the `bevy_render` feature, which is on by default.
+#[derive(Debug, Component, Clone)]
+pub enum PietRenderTarget {
+    Window,
+    #[cfg(feature = "bevy_render")]
+    Image(Handle<Image>),
+}
+
+impl Default for PietRenderTarget {
+    fn default() -> Self {
+        PietRenderTarget::Window
+    }
+}
+
+impl PietRenderTarget {
+    pub fn is_window(&self) -> bool {
+        matches!(self, PietRenderTarget::Window)
+    }
+}
+
+/// A camera rendering into an image, in the render world.
+#[derive(PartialEq)]
+pub(crate) struct ImageTarget {
+    /// The `Image` asset rendered into.
+    pub(crate) handle: HandleId,
+    /// Identifies the image while it is rendered by the backend.
+    pub(crate) id: u64,
+    /// Transform from world coordinates to image pixels.
+    pub(crate) view: Affine,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+/// The image targets of the current frame.
+#[derive(Default)]
+pub(crate) struct ExtractedImageTargets {
+    pub(crate) targets: Vec<ImageTarget>,
+}
+
+/// Pixels rendered into image targets, waiting to be written into the
+/// `Image` assets of the app world.
+#[derive(Default)]
+pub(crate) struct RenderedImages(pub(crate) Vec<(HandleId, Vec<u8>)>);
+
+#[cfg(feature = "bevy_render")]
+pub(crate) fn extract_image_targets(
+    mut render_world: ResMut<RenderWorld>,
+    cameras: Query<(&PietCamera2d, &GlobalTransform, &PietRenderTarget)>,
+    images: Option<Res<Assets<Image>>>,
+    coordinate_system: Res<PietCoordinateSystem>,
+) {
+    let mut targets = Vec::new();
+    if let Some(images) = images {
+        for (camera, transform, target) in cameras.iter() {
+            let handle = match target {
+                PietRenderTarget::Image(handle) => handle,
+                PietRenderTarget::Window => continue,
+            };
+            // Not loaded yet, or already removed
+            let image = match images.get(handle) {
+                Some(image) => image,
+                None => continue,
+            };
+            let size = image.texture_descriptor.size;
+            let view = camera.view_affine(
+                transform,
+                *coordinate_system,
+                Vec2::new(size.width as f32, size.height as f32),
+            );
+            let mut hasher = DefaultHasher::new();
+            handle.id.hash(&mut hasher);
+            targets.push(ImageTarget {
+                handle: handle.id,
+                id: hasher.finish(),
+                view,
+                width: size.width,
+                height: size.height,
+            });
+        }
+    }
+
+    let mut extracted = render_world
+        .get_resource_mut::<ExtractedImageTargets>()
+        .unwrap();
+    if extracted.targets == targets {
+        return;
+    }
+    extracted.targets = targets;
+    render_world
+        .get_resource_mut::<RedrawState>()
+        .unwrap()
+        .request();
+}
+
+/// Write the pixels rendered into image targets since the last extraction
+/// into their `Image` assets.
+#[cfg(feature = "bevy_render")]
+pub(crate) fn extract_rendered_images(
+    mut render_world: ResMut<RenderWorld>,
+    images: Option<ResMut<Assets<Image>>>,
+) {
+    let mut rendered =
+        render_world.get_resource_mut::<RenderedImages>().unwrap();
+    let mut images = match images {
+        Some(images) => images,
+        None => {
+            rendered.0.clear();
+            return;
+        }
+    };
+
+    for (handle, data) in rendered.0.drain(..) {
+        if let Some(image) = images.get_mut(handle) {
+            if image.data.len() == data.len() {
+                image.data = data;
+            } else {
+                warn!(
+                    "Piet render target image is not RGBA8 or was resized, \
+                     skipping its update"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "bevy_render"))]
+mod tests {
+    use bevy::{
+        asset::AssetPlugin,
+        render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    };
+    use piet_gpu::Color;
+
+    use super::*;
+    use crate::{
+        PietCamera2dBundle, PietPainter, PietRenderMode, PietRenderPlugin,
+        PietRenderSettings,
+    };
+
+    /// Fill the left half of an 8 by 8 pixel view around the origin.
+    fn paint_left_half(mut painter: PietPainter) {
+        painter.fill_rect(
+            Vec2::new(-4.0, -4.0),
+            Vec2::new(0.0, 4.0),
+            Color::rgb8(0, 0xff, 0),
+        );
+    }
+
+    #[test]
+    fn renders_into_image_assets() {
+        let mut app = App::new();
+        app.insert_resource(PietRenderMode::Cpu {
+            width: 16,
+            height: 16,
+        })
+        .insert_resource(PietRenderSettings {
+            clear_color: Color::rgb8(0xff, 0, 0),
+            ..Default::default()
+        })
+        .add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin)
+        .add_plugin(PietRenderPlugin::default())
+        .add_system(paint_left_half);
+
+        let image = Image::new_fill(
+            Extent3d {
+                width: 8,
+                height: 8,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 0],
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let handle = app.world.resource_mut::<Assets<Image>>().add(image);
+        app.world.spawn().insert_bundle(PietCamera2dBundle {
+            target: PietRenderTarget::Image(handle.clone()),
+            ..Default::default()
+        });
+
+        // The pixels are written into the asset when the next frame is
+        // extracted.
+        app.update();
+        app.update();
+
+        let images = app.world.resource::<Assets<Image>>();
+        let pixel = |x: usize, y: usize| {
+            let i = (y * 8 + x) * 4;
+            images.get(&handle).unwrap().data[i..i + 4].to_vec()
+        };
+        assert_eq!(pixel(1, 4), [0, 0xff, 0, 0xff]);
+        assert_eq!(pixel(6, 4), [0xff, 0, 0, 0xff]);
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+#[cfg(not(feature = "bevy_render"))]
+use bevy::ecs::entity::Entities;
+
+#[cfg(feature = "bevy_render")]
+type NodeChanged = Or<(Changed<Visibility>, Changed<Parent>)>;
+
+/// Looks up whether an entity should be drawn, for use in extract systems.
+///
+/// An entity is visible when it and all of its ancestors are visible, so
+/// hiding a parent hides its children too. Entities without `Visibility`
+/// count as visible. The hierarchy is walked here rather than read from
+/// `ComputedVisibility`, which is only kept up to date by bevy's own
+/// renderer and its cameras.
+///
+/// `Visibility` comes with bevy's render crate, without the `bevy_render`
+/// feature every entity is visible.
+#[derive(SystemParam)]
+pub struct PietVisibility<'w, 's> {
+    #[cfg(feature = "bevy_render")]
+    nodes:
+        Query<'w, 's, (Option<&'static Visibility>, Option<&'static Parent>)>,
+    #[cfg(feature = "bevy_render")]
+    changed_nodes: Query<'w, 's, (), NodeChanged>,
+    #[cfg(feature = "bevy_render")]
+    with_visibility: Query<'w, 's, (), With<Visibility>>,
+    #[cfg(feature = "bevy_render")]
+    with_parent: Query<'w, 's, (), With<Parent>>,
+    /// How many entities had a `Visibility` and a `Parent` during the last
+    /// run.
+    #[cfg(feature = "bevy_render")]
+    counts: Local<'s, (usize, usize)>,
+    #[cfg(not(feature = "bevy_render"))]
+    _entities: &'w Entities,
+    #[cfg(not(feature = "bevy_render"))]
+    _state: Local<'s, ()>,
+}
+
+#[cfg(feature = "bevy_render")]
+impl<'w, 's> PietVisibility<'w, 's> {
+    pub fn is_visible(&self, entity: Entity) -> bool {
+        let mut current = Some(entity);
+        while let Some(entity) = current {
+            match self.nodes.get(entity) {
+                Ok((visibility, parent)) => {
+                    if let Some(visibility) = visibility {
+                        if !visibility.is_visible {
+                            return false;
+                        }
+                    }
+                    current = parent.map(|parent| **parent);
+                }
+                Err(_) => return true,
+            }
+        }
+        true
+    }
+
+    /// Whether the visibility of any entity may have changed since the last
+    /// run of the system, because a `Visibility` or `Parent` was changed,
+    /// added or removed somewhere. Extract systems that keep their state
+    /// across frames only have to check visibility again then. Call it once
+    /// per run.
+    ///
+    /// Removals are found by counting, since `RemovedComponents` is already
+    /// cleared when extract systems run. A removal can only go unnoticed in
+    /// the count when another entity gained the component, which shows up as
+    /// a change.
+    pub fn hierarchy_changed(&mut self) -> bool {
+        let counts = (
+            self.with_visibility.iter().count(),
+            self.with_parent.iter().count(),
+        );
+        let counts_changed = counts != *self.counts;
+        *self.counts = counts;
+        counts_changed || !self.changed_nodes.is_empty()
+    }
+}
+
+#[cfg(not(feature = "bevy_render"))]
+impl<'w, 's> PietVisibility<'w, 's> {
+    pub fn is_visible(&self, _entity: Entity) -> bool {
+        true
+    }
+
+    /// Never true, nothing can hide an entity without the `bevy_render`
+    /// feature.
+    pub fn hierarchy_changed(&mut self) -> bool {
+        false
+    }
+}
+
+#[cfg(all(test, feature = "bevy_render"))]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+
+    #[test]
+    fn notices_changed_and_removed_visibility() {
+        let mut world = World::new();
+        let mut state = SystemState::<PietVisibility>::new(&mut world);
+        let mut hierarchy_changed = |world: &mut World| {
+            let changed = state.get_mut(world).hierarchy_changed();
+            world.clear_trackers();
+            changed
+        };
+        assert!(!hierarchy_changed(&mut world));
+
+        let entity = world.spawn().insert(Visibility::default()).id();
+        assert!(hierarchy_changed(&mut world));
+        assert!(!hierarchy_changed(&mut world));
+
+        world.get_mut::<Visibility>(entity).unwrap().is_visible = false;
+        assert!(hierarchy_changed(&mut world));
+        assert!(!hierarchy_changed(&mut world));
+
+        world.entity_mut(entity).remove::<Visibility>();
+        assert!(hierarchy_changed(&mut world));
+        assert!(!hierarchy_changed(&mut world));
+    }
+}
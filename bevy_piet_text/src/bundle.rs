@@ -10,4 +10,6 @@ pub struct TextLabelBundle {
     pub text_label: TextLabel,
     pub transform: Transform,
     pub global_transform: GlobalTransform,
+    #[cfg(feature = "bevy_render")]
+    pub visibility: Visibility,
 }
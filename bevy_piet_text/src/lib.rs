@@ -1,6 +1,6 @@
 use bevy::prelude::*;
-use bevy_piet_render::{PietRenderApp, PietRenderStage};
-use render::{extract_text_labels, prepare_text_labels};
+use bevy_piet_render::{PietDrawSystem, PietRenderApp, PietRenderStage};
+use render::{extract_text_labels, prepare_text_labels, ExtractedTextLabels};
 
 mod bundle;
 mod render;
@@ -16,13 +16,14 @@ impl Plugin for PietTextPlugin {
         //     .init_asset_loader::<SvgAssetLoader>();
         if let Ok(render_app) = app.get_sub_app_mut(PietRenderApp) {
             render_app
+                .init_resource::<ExtractedTextLabels>()
                 .add_system_to_stage(
                     PietRenderStage::Extract,
                     extract_text_labels,
                 )
                 .add_system_to_stage(
                     PietRenderStage::Prepare,
-                    prepare_text_labels,
+                    prepare_text_labels.label(PietDrawSystem::Queue),
                 );
             //     .init_resource::<ExtractedVecImgInstances>()
             //     .init_resource::<VectorImageRenderAssets>()
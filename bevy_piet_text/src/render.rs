@@ -1,43 +1,85 @@
+use std::sync::Arc;
+
 use bevy::prelude::*;
-use bevy_piet_render::{RenderCommand, RenderLayer, RenderType, RenderWorld};
+use bevy_piet_render::{
+    DrawList, ExtractedEntities, ExtractedEntitiesSync, PietLayer,
+    PietProducer, RenderCommand, RenderType, RenderWorld,
+};
 
 use crate::bundle::TextLabel;
 
+/// Layer of labels without a [`PietLayer`] component. Text is drawn on top of
+/// the default layer of everything else.
+const DEFAULT_TEXT_LAYER: PietLayer = PietLayer::FOREGROUND;
+
 pub struct ExtractedTextLabel {
-    text: String,
+    text: Arc<str>,
     transform: GlobalTransform,
 }
 
-pub struct ExtractedTextLabels {
-    text_labels: Vec<ExtractedTextLabel>,
-}
+/// The text labels of the app world, kept in the render world across frames
+/// and only updated for the entities whose components changed.
+pub type ExtractedTextLabels = ExtractedEntities<ExtractedTextLabel>;
 
 pub fn extract_text_labels(
-    query: Query<(&TextLabel, &GlobalTransform)>,
+    changed_labels: Query<
+        (
+            Entity,
+            &TextLabel,
+            &GlobalTransform,
+            ChangeTrackers<TextLabel>,
+        ),
+        Or<(Changed<TextLabel>, Changed<GlobalTransform>)>,
+    >,
+    mut entities: ExtractedEntitiesSync<(
+        With<TextLabel>,
+        With<GlobalTransform>,
+    )>,
     mut render_world: ResMut<RenderWorld>,
 ) {
-    let mut text_labels = Vec::new();
-    for (text_label, transform) in query.iter() {
-        text_labels.push(ExtractedTextLabel {
-            text: text_label.text.clone(),
-            transform: *transform,
-        })
-    }
+    let mut text_labels = render_world
+        .get_resource_mut::<ExtractedTextLabels>()
+        .unwrap();
 
-    // Copy extracted instances to render world in order to prepare
-    // all vector images extracted during this frame for rendering.
-    render_world.insert_resource(ExtractedTextLabels { text_labels });
+    for (entity, text_label, transform, label_tracker) in changed_labels.iter()
+    {
+        match text_labels.update(entity) {
+            Some(extracted) => {
+                if label_tracker.is_changed() {
+                    extracted.text = text_label.text.as_str().into();
+                }
+                extracted.transform = *transform;
+            }
+            None => text_labels.insert(
+                entity,
+                ExtractedTextLabel {
+                    text: text_label.text.as_str().into(),
+                    transform: *transform,
+                },
+            ),
+        }
+    }
+    entities.sync(&mut text_labels);
 }
 
 pub fn prepare_text_labels(
-    extracted_text_labels: ResMut<ExtractedTextLabels>,
-    mut render_commands: EventWriter<RenderCommand>,
+    mut extracted_text_labels: ResMut<ExtractedTextLabels>,
+    mut draw_list: ResMut<DrawList>,
 ) {
-    for extracted in extracted_text_labels.text_labels.iter() {
+    for (entity, extracted) in extracted_text_labels.iter_mut() {
+        if !extracted.visible {
+            continue;
+        }
+        let text_label = &extracted.data;
         let render_command =
-            RenderType::Text(extracted.text.clone(), extracted.transform);
-        render_commands
-            .send(RenderCommand::new(render_command, RenderLayer::Foreground));
+            RenderType::Text(text_label.text.clone(), text_label.transform);
+        let layer = extracted.layer.unwrap_or(DEFAULT_TEXT_LAYER);
+        draw_list.push(
+            RenderCommand::new(render_command, layer)
+                .tracked(entity, extracted.changed)
+                .producer(PietProducer::of::<TextLabel>()),
+        );
+        extracted.changed = false;
         // render_text(&mut ctx, &text_label.text,
         // text_label.transform.translation.xy());
     }
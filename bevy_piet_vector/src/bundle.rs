@@ -12,4 +12,6 @@ pub struct VecImgInstanceBundle {
     pub vector_image: Handle<VectorImage>,
     pub transform: Transform,
     pub global_transform: GlobalTransform,
+    #[cfg(feature = "bevy_render")]
+    pub visibility: Visibility,
 }
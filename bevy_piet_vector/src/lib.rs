@@ -1,10 +1,11 @@
 use bevy::prelude::*;
-use bevy_piet_render::{PietRenderApp, PietRenderStage};
+use bevy_piet_render::{PietDrawSystem, PietRenderApp, PietRenderStage};
 use render::prepare_vector_images;
 use svg_loader::SvgAssetLoader;
 use vector_image::{
     extract_vec_img_instances, extract_vec_img_render_assets,
-    ExtractedVecImgInstances, VectorImage, VectorImageRenderAssets,
+    ChangedVectorImages, ExtractedVecImgInstances, VectorImage,
+    VectorImageRenderAssets,
 };
 
 mod bundle;
@@ -25,6 +26,7 @@ impl Plugin for PietVectorPlugin {
             render_app
                 .init_resource::<ExtractedVecImgInstances>()
                 .init_resource::<VectorImageRenderAssets>()
+                .init_resource::<ChangedVectorImages>()
                 .add_system_to_stage(
                     PietRenderStage::Extract,
                     extract_vec_img_render_assets,
@@ -35,7 +37,7 @@ impl Plugin for PietVectorPlugin {
                 )
                 .add_system_to_stage(
                     PietRenderStage::Prepare,
-                    prepare_vector_images,
+                    prepare_vector_images.label(PietDrawSystem::Queue),
                 );
         }
     }
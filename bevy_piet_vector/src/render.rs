@@ -1,45 +1,45 @@
-use std::cmp::Ordering;
-
 use bevy::prelude::*;
-use bevy_piet_render::{RenderCommand, RenderLayer, RenderType};
-
-use piet_gpu::PietGpuRenderContext;
+use bevy_piet_render::{
+    DrawList, PietLayer, PietProducer, RenderCommand, RenderType,
+};
 
-use crate::vector_image::{ExtractedVecImgInstances, VectorImageRenderAssets};
+use crate::{
+    vector_image::{
+        ChangedVectorImages, ExtractedVecImgInstances, VectorImageRenderAssets,
+    },
+    VectorImageInstance,
+};
 
 pub fn prepare_vector_images(
     mut extracted_app_world_vecs: ResMut<ExtractedVecImgInstances>,
     vec_images: Res<VectorImageRenderAssets>,
-    mut render_commands: EventWriter<RenderCommand>,
-    _ctx: ResMut<PietGpuRenderContext>,
+    changed_vec_images: Res<ChangedVectorImages>,
+    mut draw_list: ResMut<DrawList>,
 ) {
-    // Sort images by z for correct transparency and then by handle to improve
-    // batching
-    extracted_app_world_vecs.instances.sort_unstable_by(|a, b| {
-        match a
-            .transform
-            .translation
-            .z
-            .partial_cmp(&b.transform.translation.z)
-        {
-            Some(Ordering::Equal) | None => {
-                a.vec_image_handle_id.cmp(&b.vec_image_handle_id)
-            }
-            Some(other) => other,
+    for (entity, extracted) in extracted_app_world_vecs.iter_mut() {
+        if !extracted.visible {
+            continue;
         }
-    });
-
-    for extracted in extracted_app_world_vecs.instances.iter() {
+        let instance = &extracted.data;
         if let Some(vec_image) =
-            vec_images.get(&Handle::weak(extracted.vec_image_handle_id))
+            vec_images.get(&Handle::weak(instance.vec_image_handle_id))
         {
             let render_command = RenderType::Svg(
                 vec_image.svg.clone(),
-                extracted.transform,
-                extracted.vec_image_inst.center,
+                instance.transform,
+                instance.vec_image_inst.center,
+            );
+            let changed = extracted.changed
+                || changed_vec_images
+                    .handles
+                    .contains(&instance.vec_image_handle_id);
+            let layer = extracted.layer.unwrap_or(PietLayer::MIDDLE);
+            draw_list.push(
+                RenderCommand::new(render_command, layer)
+                    .tracked(entity, changed)
+                    .producer(PietProducer::of::<VectorImageInstance>()),
             );
-            render_commands
-                .send(RenderCommand::new(render_command, RenderLayer::Middle))
+            extracted.changed = false;
             // render_svg(
             //     &vec_image.svg,
             //     &mut ctx,
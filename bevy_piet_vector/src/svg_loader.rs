@@ -1,10 +1,14 @@
-use anyhow::Result;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
 
 use bevy::{
     asset::{AssetLoader, LoadContext, LoadedAsset},
     utils::BoxedFuture,
 };
-use piet_gpu::PicoSvg;
+use bevy_piet_render::{PathItem, VectorGraphic};
+use kurbo::{Affine, BezPath};
+use piet_gpu::Color;
 
 use crate::vector_image::VectorImage;
 
@@ -14,20 +18,16 @@ pub struct SvgAssetLoader;
 impl AssetLoader for SvgAssetLoader {
     fn load<'a>(
         &'a self,
-        _bytes: &'a [u8],
+        bytes: &'a [u8],
         load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<()>> {
         Box::pin(async move {
-            let mut svg_src_path =
-                load_context.path().to_string_lossy().to_string();
-            svg_src_path =
-                "assets/".to_string() + &svg_src_path.replace("\\", "/");
-
-            let xml_str = std::fs::read_to_string(&svg_src_path).unwrap();
-            let svg = PicoSvg::load(&xml_str, 1.0).unwrap();
+            let xml_str = std::str::from_utf8(bytes)?;
+            let svg = load_svg(xml_str, 1.0)?;
 
-            load_context
-                .set_default_asset(LoadedAsset::new(VectorImage { svg }));
+            load_context.set_default_asset(LoadedAsset::new(VectorImage {
+                svg: Arc::new(svg),
+            }));
 
             Ok(())
         })
@@ -37,3 +37,126 @@ impl AssetLoader for SvgAssetLoader {
         &["svg"]
     }
 }
+
+/// Parse the `path` elements of an SVG document into a [`VectorGraphic`].
+/// Supports the same subset of SVG as piet-gpu's `PicoSvg`: solid `fill` and
+/// `stroke` colors and `stroke-width`.
+fn load_svg(xml_str: &str, scale: f64) -> Result<VectorGraphic> {
+    let doc = roxmltree::Document::parse(xml_str)?;
+    let mut items = Vec::new();
+    for el in doc
+        .root_element()
+        .descendants()
+        .filter(|node| node.has_tag_name("path"))
+    {
+        let d = el
+            .attribute("d")
+            .ok_or_else(|| anyhow!("missing 'd' attribute"))?;
+        let path = Affine::scale(scale) * BezPath::from_svg(d)?;
+        if let Some(color) = el.attribute("fill").and_then(parse_color) {
+            items.push(PathItem::Fill {
+                path: path.clone(),
+                color,
+            });
+        }
+        if let Some(color) = el.attribute("stroke").and_then(parse_color) {
+            let width = match el.attribute("stroke-width") {
+                Some(width) => width.parse::<f64>()?,
+                None => 1.0,
+            };
+            items.push(PathItem::Stroke {
+                path,
+                color,
+                width: scale * width,
+            });
+        }
+    }
+    Ok(VectorGraphic::new(items))
+}
+
+/// Parse a `#rgb`, `#rrggbb` or `rgb(r, g, b)` color. Returns `None` for
+/// `none`, and a translucent magenta for anything unsupported.
+fn parse_color(color: &str) -> Option<Color> {
+    let color = color.trim();
+    if color == "none" {
+        return None;
+    }
+
+    let rgb = if let Some(hex) = color.strip_prefix('#') {
+        let value = u32::from_str_radix(hex, 16).ok();
+        match (hex.len(), value) {
+            (3, Some(v)) => Some(
+                (v >> 8) * 0x110000
+                    + ((v >> 4) & 0xf) * 0x1100
+                    + (v & 0xf) * 0x11,
+            ),
+            (6, Some(v)) => Some(v),
+            _ => None,
+        }
+    } else if let Some(args) = color
+        .strip_prefix("rgb(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let channels = args
+            .split(',')
+            .map(|c| c.trim().parse::<u8>().ok())
+            .collect::<Option<Vec<_>>>();
+        match channels.as_deref() {
+            Some([r, g, b]) => {
+                Some(((*r as u32) << 16) | ((*g as u32) << 8) | *b as u32)
+            }
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    Some(match rgb {
+        Some(rgb) => Color::from_rgba32_u32((rgb << 8) | 0xff),
+        None => Color::from_rgba32_u32(0xff00ff80),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_piet_render::DrawOp;
+    use kurbo::Rect;
+
+    use super::*;
+
+    #[test]
+    fn parses_colors() {
+        let rgb = |color: Option<Color>| color.map(|c| c.as_rgba_u32());
+        assert_eq!(rgb(parse_color("#f80")), Some(0xff8800ff));
+        assert_eq!(rgb(parse_color(" #12ab34 ")), Some(0x12ab34ff));
+        assert_eq!(rgb(parse_color("rgb(1, 2, 255)")), Some(0x0102ffff));
+        assert_eq!(rgb(parse_color("none")), None);
+        assert_eq!(rgb(parse_color("red")), Some(0xff00ff80));
+        assert_eq!(rgb(parse_color("#12345")), Some(0xff00ff80));
+    }
+
+    #[test]
+    fn loads_filled_and_stroked_paths() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg">
+            <g>
+                <path d="M0 0 L10 0 L10 10 Z" fill="#ff0000"/>
+                <path d="M0 0 L20 20" fill="none" stroke="#00f"
+                    stroke-width="2"/>
+            </g>
+        </svg>"##;
+        let graphic = load_svg(svg, 2.0).unwrap();
+
+        match graphic.fragment().ops() {
+            [DrawOp::Fill(..), DrawOp::Stroke(_, _, width)] => {
+                assert_eq!(*width, 4.0)
+            }
+            _ => panic!("expected a fill and a stroke"),
+        }
+        assert_eq!(graphic.bounds(), Some(Rect::new(-4.0, -4.0, 44.0, 44.0)));
+    }
+
+    #[test]
+    fn rejects_paths_without_data() {
+        assert!(load_svg("<svg><path fill=\"#fff\"/></svg>", 1.0).is_err());
+    }
+}
@@ -1,18 +1,23 @@
+use std::sync::Arc;
+
 use bevy::{
     asset::HandleId,
     prelude::*,
     reflect::TypeUuid,
     utils::{HashMap, HashSet},
 };
-use bevy_piet_render::RenderWorld;
-use piet_gpu::PicoSvg;
+use bevy_piet_render::{
+    ExtractedEntities, ExtractedEntitiesSync, RenderWorld, VectorGraphic,
+};
 
 use crate::VectorImageInstance;
 
+/// A loaded SVG. The graphic is shared, so cloning the asset into the render
+/// world and drawing it for every instance copies no paths.
 #[derive(Clone, TypeUuid)]
 #[uuid = "6ea26da6-6cf8-4ea2-9986-1d7bf6c17d6f"]
 pub struct VectorImage {
-    pub svg: PicoSvg,
+    pub svg: Arc<VectorGraphic>,
 }
 
 /// All the data extracted from a vector image instance necessary to render.
@@ -23,41 +28,63 @@ pub struct ExtractedVecImgInstance {
     pub vec_image_inst: VectorImageInstance,
 }
 
-/// Resource for storing all the vector image instances extracted at the current
-/// frame.,
-#[derive(Default)]
-pub struct ExtractedVecImgInstances {
-    pub instances: Vec<ExtractedVecImgInstance>,
-}
+/// Resource for storing all vector image instances of the app world. It is
+/// kept across frames and only updated for the entities whose components
+/// changed.
+pub type ExtractedVecImgInstances = ExtractedEntities<ExtractedVecImgInstance>;
 
-/// Extract all vector image instances from the "app world" and copy them to the
-/// piet "render world".
+/// Extract the vector image instances that were added or changed in the
+/// "app world" into the piet "render world", and drop removed ones.
 pub fn extract_vec_img_instances(
     mut render_world: ResMut<RenderWorld>,
-    vec_img_inst_query: Query<(
-        &GlobalTransform,
-        &Handle<VectorImage>,
-        &VectorImageInstance,
+    changed_instances: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &Handle<VectorImage>,
+            &VectorImageInstance,
+        ),
+        Or<(
+            Changed<GlobalTransform>,
+            Changed<Handle<VectorImage>>,
+            Changed<VectorImageInstance>,
+        )>,
+    >,
+    mut entities: ExtractedEntitiesSync<(
+        With<GlobalTransform>,
+        With<Handle<VectorImage>>,
+        With<VectorImageInstance>,
     )>,
 ) {
-    let mut instances = Vec::new();
-    for (transform, handle, vec_image_inst) in vec_img_inst_query.iter() {
-        instances.push(ExtractedVecImgInstance {
-            transform: *transform,
-            vec_image_handle_id: handle.id,
-            vec_image_inst: *vec_image_inst,
-        })
-    }
+    let mut instances = render_world
+        .get_resource_mut::<ExtractedVecImgInstances>()
+        .unwrap();
 
-    // Copy extracted instances to render world in order to prepare
-    // all vector images extracted during this frame for rendering.
-    render_world.insert_resource(ExtractedVecImgInstances { instances });
+    for (entity, transform, handle, vec_image_inst) in changed_instances.iter()
+    {
+        instances.insert(
+            entity,
+            ExtractedVecImgInstance {
+                transform: *transform,
+                vec_image_handle_id: handle.id,
+                vec_image_inst: *vec_image_inst,
+            },
+        );
+    }
+    entities.sync(&mut instances);
 }
 
 /// Stores all render data representations of VectorImageRenderAssets as long as
 /// they exist.
 pub type VectorImageRenderAssets = HashMap<Handle<VectorImage>, VectorImage>;
 
+/// Vector images created or modified during the current frame. Instances of
+/// them have to be drawn again even if they did not change themselves.
+#[derive(Default)]
+pub struct ChangedVectorImages {
+    pub handles: HashSet<HandleId>,
+}
+
 /// This system extracts all crated or modified assets of the corresponding
 /// [`VectorImage`] type into the piet "render world".
 pub fn extract_vec_img_render_assets(
@@ -83,9 +110,12 @@ pub fn extract_vec_img_render_assets(
         }
     }
 
+    let mut changed = HashSet::default();
     for handle in new_assets.drain() {
         if let Some(asset) = assets.get(handle) {
             vec_image_render_assets.insert(handle.clone_weak(), asset.clone());
+            changed.insert(handle.id);
         }
     }
+    render_world.insert_resource(ChangedVectorImages { handles: changed });
 }